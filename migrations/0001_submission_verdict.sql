@@ -0,0 +1,3 @@
+-- Columns the judge writes besides status, score, time, memory and result.
+ALTER TABLE submission ADD COLUMN IF NOT EXISTS verdict TEXT;
+ALTER TABLE submission ADD COLUMN IF NOT EXISTS public_result JSONB;
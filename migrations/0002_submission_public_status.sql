@@ -0,0 +1,2 @@
+-- The status for contestants, next to the full status in `status`.
+ALTER TABLE submission ADD COLUMN IF NOT EXISTS public_status TEXT;
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{ anyhow, Context, Result };
+use lapin::{ Channel, Connection };
+use log::{ info, warn };
+use tokio::select;
+use tokio::task::JoinSet;
+use tokio::time::{ interval, sleep, Instant };
+use tokio_postgres::Client;
+
+use crate::isolate::{ BOXES_PER_JOB, JOB_BOX_START };
+use crate::rbmq::{ self, Priority };
+use crate::settings::settings;
+use crate::AppState;
+
+/// Time between two attempts to connect at startup.
+const STARTUP_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// First and largest wait before reconnecting, doubled after every failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How often the connection and the channel of the HTTP handlers are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A consumer the broker keeps subscribed.
+#[derive(Debug, Clone)]
+enum Worker {
+    /// Takes messages from the first queue that has any.
+    Submission {
+        queue_names: Vec<String>,
+        consumer_tag: String,
+    },
+    Job {
+        queue_name: String,
+        consumer_tag: String,
+        box_id: u64,
+    },
+}
+
+/// Contest workers judge practice submissions too, but only while no contest submission is
+/// waiting, so the practice queue is drained even without workers reserved for it.
+fn workers() -> Vec<Worker> {
+    let contest = rbmq::submission_queue_name(Priority::Contest);
+    let practice = rbmq::submission_queue_name(Priority::Practice);
+
+    let mut workers = vec![];
+    for i in 0..settings().max_worker {
+        workers.push(Worker::Submission {
+            queue_names: vec![contest.clone(), practice.clone()],
+            consumer_tag: format!("consumer {}", i),
+        });
+    }
+    for i in 0..settings().max_practice_worker {
+        workers.push(Worker::Submission {
+            queue_names: vec![practice.clone()],
+            consumer_tag: format!("practice consumer {}", i),
+        });
+    }
+    // Jobs get their own, smaller pool so they never take a worker away from judging.
+    for i in 0..settings().max_job_worker {
+        workers.push(Worker::Job {
+            queue_name: settings().job_queue_name.clone(),
+            consumer_tag: format!("job consumer {}", i),
+            box_id: JOB_BOX_START + i * BOXES_PER_JOB,
+        });
+    }
+    workers
+}
+
+/// Opens the channel HTTP handlers publish on.
+async fn open_channel(connection: &Connection) -> Result<Channel> {
+    let channel = rbmq::create_channel(connection, None).await?;
+    rbmq::enable_confirms(&channel).await?;
+
+    Ok(channel)
+}
+
+/// Connects to RabbitMQ and declares every queue. Returns the connection and a channel for
+/// the HTTP handlers. Every call opens a new connection and closes it again on failure, so a
+/// declaration that closed its channel does not leave the next attempt a dead one.
+async fn connect() -> Result<(Connection, Channel)> {
+    let rbmq_url = settings().rbmq_url.as_deref().ok_or(anyhow!("rbmq_url is not set"))?;
+    let connection = rbmq::get_connection(rbmq_url).await?;
+
+    match declare_queues(&connection).await {
+        Ok(channel) => Ok((connection, channel)),
+        Err(err) => {
+            let _ = connection.close(0, "Failed to declare the queues").await;
+            Err(err)
+        }
+    }
+}
+
+async fn declare_queues(connection: &Connection) -> Result<Channel> {
+    let channel = open_channel(connection).await?;
+    // RabbitMQ refuses to redeclare a queue with other options and closes the channel.
+    let hint = |queue_name: &str| {
+        format!(
+            "Failed to declare queue {:?}. A queue left by an older version must be deleted \
+             once, see the README",
+            queue_name
+        )
+    };
+
+    for priority in [Priority::Contest, Priority::Practice] {
+        let queue_name = rbmq::submission_queue_name(priority);
+        rbmq::create_queue(channel.clone(), queue_name.clone()).await
+            .with_context(|| hint(&queue_name))?;
+        rbmq::create_retry_queues(channel.clone(), queue_name.clone()).await
+            .with_context(|| hint(&queue_name))?;
+    }
+    let job_queue_name = settings().job_queue_name.clone();
+    rbmq::create_queue(channel.clone(), job_queue_name.clone()).await
+        .with_context(|| hint(&job_queue_name))?;
+
+    Ok(channel)
+}
+
+/// Connects at startup, giving up after `max_retries` attempts. Each attempt starts over with
+/// a new connection.
+pub async fn connect_at_startup() -> Result<(Connection, Channel)> {
+    let mut retries = 0;
+    loop {
+        match connect().await {
+            Ok(connected) => {
+                return Ok(connected);
+            }
+            Err(err) => {
+                retries += 1;
+                if retries >= settings().max_retries {
+                    return Err(err);
+                }
+                warn!("Failed to connect to RabbitMQ: {:?}", err);
+                sleep(STARTUP_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Connects again until it succeeds, waiting longer after every failure.
+async fn reconnect() -> (Connection, Channel) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        sleep(backoff).await;
+        match connect().await {
+            Ok(connected) => {
+                return connected;
+            }
+            Err(err) => {
+                warn!("Failed to reconnect to RabbitMQ, retrying in {:?}: {}", backoff, err);
+                backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Subscribes a worker on a channel of its own after `delay`. The task returns the worker's
+/// index once the consumer has stopped, also when it panicked.
+async fn start_worker(
+    connection: &Connection,
+    running: &mut JoinSet<usize>,
+    index: usize,
+    worker: Worker,
+    db_client: Arc<Client>,
+    delay: Duration
+) -> Result<()> {
+    let channel = rbmq::create_channel(connection, Some(settings().prefetch)).await?;
+    if let Worker::Submission { .. } = worker {
+        rbmq::enable_confirms(&channel).await?;
+    }
+
+    running.spawn(async move {
+        sleep(delay).await;
+        let (consumer_tag, consumed) = match worker {
+            Worker::Submission { queue_names, consumer_tag } => {
+                let consumed = tokio::spawn(
+                    rbmq::create_consumer(channel, db_client, queue_names, consumer_tag.clone())
+                ).await;
+                (consumer_tag, consumed)
+            }
+            Worker::Job { queue_name, consumer_tag, box_id } => {
+                let consumed = tokio::spawn(
+                    rbmq::create_job_consumer(channel, queue_name, consumer_tag.clone(), box_id)
+                ).await;
+                (consumer_tag, consumed)
+            }
+        };
+        match consumed {
+            Ok(Ok(())) => warn!("{:?} stopped", consumer_tag),
+            Ok(Err(err)) => warn!("{:?} stopped: {}", consumer_tag, err),
+            Err(err) => warn!("{:?} crashed: {}", consumer_tag, err),
+        }
+        index
+    });
+
+    Ok(())
+}
+
+/// Restarts workers that stop and reopens the channel of the HTTP handlers while the
+/// connection is up. A worker that keeps stopping waits longer before every restart, and
+/// starts over with `MIN_BACKOFF` once it has run for `MAX_BACKOFF`. Returns once the
+/// connection is lost.
+async fn watch(
+    connection: &Connection,
+    state: &AppState,
+    running: &mut JoinSet<usize>,
+    workers: &[Worker],
+    db_client: &Arc<Client>
+) {
+    let mut check = interval(CHECK_INTERVAL);
+    let mut backoff = vec![MIN_BACKOFF; workers.len()];
+    let mut started_at = vec![Instant::now(); workers.len()];
+    loop {
+        select! {
+            Some(stopped) = running.join_next() => {
+                if !connection.status().connected() {
+                    return;
+                }
+                let Ok(index) = stopped else {
+                    continue;
+                };
+                if started_at[index].elapsed() >= MAX_BACKOFF {
+                    backoff[index] = MIN_BACKOFF;
+                }
+                let delay = backoff[index];
+                backoff[index] = std::cmp::min(delay * 2, MAX_BACKOFF);
+                started_at[index] = Instant::now() + delay;
+
+                let started = start_worker(
+                    connection,
+                    running,
+                    index,
+                    workers[index].clone(),
+                    db_client.clone(),
+                    delay
+                ).await;
+                if let Err(err) = started {
+                    warn!("Failed to restart a RabbitMQ consumer: {}", err);
+                    return;
+                }
+            }
+            _ = check.tick() => {
+                if !connection.status().connected() {
+                    return;
+                }
+                if !state.channel().status().connected() {
+                    match open_channel(connection).await {
+                        Ok(channel) => state.set_channel(channel),
+                        Err(err) => {
+                            warn!("Failed to reopen the RabbitMQ channel: {}", err);
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Keeps every consumer subscribed and the channel of the HTTP handlers open. When the
+/// connection is lost it reconnects with backoff, declares the queues again and hands the new
+/// channel to the handlers. The consumers are only subscribed again once the old ones have
+/// stopped, so no two runs share an isolate box. Never returns.
+pub async fn supervise(mut connection: Connection, state: Arc<AppState>, db_client: Arc<Client>) {
+    let workers = workers();
+    loop {
+        let mut running = JoinSet::new();
+        let mut started = Ok(());
+        for (index, worker) in workers.iter().enumerate() {
+            started = start_worker(
+                &connection,
+                &mut running,
+                index,
+                worker.clone(),
+                db_client.clone(),
+                Duration::ZERO
+            ).await;
+            if started.is_err() {
+                break;
+            }
+        }
+        match started {
+            Ok(()) => watch(&connection, &state, &mut running, &workers, &db_client).await,
+            Err(err) => warn!("Failed to start the RabbitMQ consumers: {}", err),
+        }
+
+        warn!("Lost the RabbitMQ connection, reconnecting");
+        let _ = connection.close(0, "Reconnecting").await;
+
+        let channel;
+        (connection, channel) = reconnect().await;
+        state.set_channel(channel);
+        info!(" [x] Reconnected to RabbitMQ");
+
+        // Consumers that are still judging finish their run and stop, as their channel is
+        // gone. Their messages come back unacknowledged and are judged again. Aborting them
+        // instead would leave their isolate processes running in the boxes.
+        if !running.is_empty() {
+            info!(" [x] Waiting for {} consumers to stop", running.len());
+        }
+        while running.join_next().await.is_some() {}
+    }
+}
@@ -0,0 +1,242 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use clap::{ Parser, Subcommand };
+use serde_json::json;
+
+use crate::helper::{ get_language_config, get_task_config };
+use crate::isolate::{ CLI_BOX_ID, JOB_BOX_START };
+use crate::runner::{ self, JudgeResult, Verdict };
+use crate::settings::SettingsArgs;
+use crate::{ import, validate };
+
+/// Exit codes of `judge`.
+const EXIT_ACCEPTED: i32 = 0;
+const EXIT_REJECTED: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Programming judge system")]
+pub struct Cli {
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+    /// Starts the server when left out
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Imports a package from a zip or a directory into the tasks directory
+    Import {
+        #[arg(value_parser = ["polygon", "kattis", "cms"])]
+        format: String,
+        path: PathBuf,
+        task_id: String,
+    },
+    /// Judges a solution locally, without RabbitMQ or PostgreSQL
+    ///
+    /// Exits with 0 when the solution is accepted, 1 when it is not and 2 when it could not
+    /// be judged.
+    Judge {
+        /// Task id in the tasks directory
+        #[arg(long)]
+        task: String,
+        /// Language in config.json, guessed from the file extension when left out
+        #[arg(long)]
+        lang: Option<String>,
+        /// Prints the result as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        /// isolate box to judge in, one no server worker uses by default
+        #[arg(long, default_value_t = CLI_BOX_ID)]
+        box_id: u64,
+        file: PathBuf,
+    },
+    /// Checks a task directory, for example in CI of a task repository
+    ///
+    /// Exits with 1 when there are errors, or warnings with --strict.
+    LintTask {
+        dir: PathBuf,
+        /// Prints the report as JSON
+        #[arg(long)]
+        json: bool,
+        /// Fails on warnings too
+        #[arg(long)]
+        strict: bool,
+        /// Test files above this size in MB are flagged
+        #[arg(long, default_value_t = 16)]
+        max_file_size: u64,
+    },
+}
+
+/// Runs a subcommand and returns the exit code.
+pub async fn run_command(command: Command) -> i32 {
+    match command {
+        Command::Import { format, path, task_id } => import_command(&format, &path, &task_id),
+        Command::Judge { task, lang, json, box_id, file } => {
+            judge_command(task, lang, json, box_id, &file).await
+        }
+        Command::LintTask { dir, json, strict, max_file_size } => {
+            lint_task_command(&dir, json, strict, max_file_size)
+        }
+    }
+}
+
+fn import_command(format: &str, path: &Path, task_id: &str) -> i32 {
+    match import::import_path(format, path, task_id) {
+        Ok(warnings) => {
+            for warning in warnings {
+                println!("warning: {}", warning);
+            }
+            for problem in validate::validate_task_id(task_id) {
+                println!("problem: {}", problem);
+            }
+            0
+        }
+        Err(err) => {
+            eprintln!("Import failed: {}", err);
+            1
+        }
+    }
+}
+
+async fn judge_command(
+    task_id: String,
+    language: Option<String>,
+    json: bool,
+    box_id: u64,
+    file: &Path
+) -> i32 {
+    let language = match language {
+        Some(language) => language,
+        None => {
+            let ext = file.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+            match import::language_of(ext) {
+                Some((language, _)) => language.to_string(),
+                None => {
+                    eprintln!("Cannot tell the language of {}, use --lang", file.display());
+                    return EXIT_ERROR;
+                }
+            }
+        }
+    };
+    if let Err(err) = get_language_config(&language) {
+        eprintln!("{}: {}", err, language);
+        return EXIT_ERROR;
+    }
+    if box_id >= JOB_BOX_START && box_id != CLI_BOX_ID {
+        eprintln!("--box-id must be below {} or {}", JOB_BOX_START, CLI_BOX_ID);
+        return EXIT_ERROR;
+    }
+    let code = match fs::read_to_string(file) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Cannot read {}: {}", file.display(), err);
+            return EXIT_ERROR;
+        }
+    };
+
+    let judge_result = match runner::run(task_id.clone(), box_id, code, language, None).await {
+        Ok(judge_result) => judge_result,
+        Err(err) => {
+            eprintln!("Judge Error: {}", err);
+            return EXIT_ERROR;
+        }
+    };
+    let full_score = get_task_config(task_id).map(|task_config| task_config.full_score).ok();
+
+    if json {
+        print_json(&judge_result, full_score);
+    } else {
+        print_table(&judge_result, full_score);
+    }
+
+    match judge_result.verdict {
+        Verdict::Accepted => EXIT_ACCEPTED,
+        Verdict::TaskMisconfigured | Verdict::JudgeError => EXIT_ERROR,
+        _ => EXIT_REJECTED,
+    }
+}
+
+fn lint_task_command(dir: &Path, json: bool, strict: bool, max_file_size: u64) -> i32 {
+    let report = validate::lint_task(dir, max_file_size * 1000 * 1000);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+    } else {
+        for error in &report.errors {
+            println!("error: {}", error);
+        }
+        for warning in &report.warnings {
+            println!("warning: {}", warning);
+        }
+        println!(
+            "{}: {} errors, {} warnings",
+            dir.display(),
+            report.errors.len(),
+            report.warnings.len()
+        );
+    }
+
+    let failed = !report.errors.is_empty() || (strict && !report.warnings.is_empty());
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+fn print_json(judge_result: &JudgeResult, full_score: Option<u64>) {
+    let output =
+        json!({
+        "status": judge_result.status,
+        "verdict": judge_result.verdict.code(),
+        "score": judge_result.score,
+        "full_score": full_score,
+        "time": judge_result.time,
+        "memory": judge_result.memory,
+        "result": judge_result.result,
+    });
+    println!("{}", serde_json::to_string_pretty(&output).unwrap_or_default());
+}
+
+fn print_table(judge_result: &JudgeResult, full_score: Option<u64>) {
+    if !judge_result.result.is_empty() {
+        println!(
+            "{:>5}  {:>7}  {:<7}  {:>5}  {:>8}  {:>10}",
+            "Test",
+            "Subtask",
+            "Verdict",
+            "Score",
+            "Time",
+            "Memory"
+        );
+    }
+    for run_result in &judge_result.result {
+        let subtask = match run_result.subtask_index {
+            0 => "-".to_string(),
+            subtask_index => subtask_index.to_string(),
+        };
+        println!(
+            "{:>5}  {:>7}  {:<7}  {:>5}  {:>7.3}s  {:>7} KB",
+            run_result.test_index,
+            subtask,
+            run_result.verdict.code(),
+            run_result.score,
+            run_result.time,
+            run_result.memory
+        );
+    }
+
+    let score = match full_score {
+        Some(full_score) => format!("{}/{}", judge_result.score, full_score),
+        None => judge_result.score.to_string(),
+    };
+    println!(
+        "{}, score {}, {} ms, {} KB",
+        judge_result.status,
+        score,
+        judge_result.time,
+        judge_result.memory
+    );
+}
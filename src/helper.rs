@@ -2,11 +2,20 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::env;
-use anyhow::Result;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, LazyLock, Mutex, RwLock };
+use anyhow::{ anyhow, Result };
+use log::info;
 
 use serde::{ Deserialize, Serialize };
+use serde_json::{ json, Value };
+use uuid::Uuid;
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::isolate::Limits;
+use crate::settings::settings;
+use crate::validate::validate_config;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct LanguageConfig {
     pub ext: String,
     pub compile: String,
@@ -18,52 +27,502 @@ pub struct Config {
     pub language: HashMap<String, LanguageConfig>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// config.json as last loaded. It is only replaced by a config that parsed and validated, so
+/// a half-written file never reaches the workers.
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+
+/// One lock per task, held while its manifest is read, changed and written back.
+static TASK_LOCKS: LazyLock<Mutex<HashMap<String, Arc<Mutex<()>>>>> = LazyLock::new(
+    Default::default
+);
+
+/// Manifests without a `version` field are version 1.
+pub const MANIFEST_VERSION: u64 = 2;
+
+fn default_manifest_version() -> u64 {
+    1
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct TaskConfig {
+    #[serde(default = "default_manifest_version")]
+    pub version: u64,
     pub time_limit: f64,
     pub memory_limit: u64,
     pub checker: String,
+    #[serde(default)]
+    pub checker_type: CheckerType,
+    #[serde(default)]
+    pub mode: JudgeMode,
+    #[serde(default)]
+    pub feedback: FeedbackLevel,
+    #[serde(default)]
     pub skip: bool,
     pub full_score: u64,
     pub num_testcases: u64,
+    #[serde(default)]
     pub subtasks: Vec<Subtask>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<u64>,
+    #[serde(default)]
+    pub exclude_sample_score: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub test_limits: HashMap<u64, LimitOverride>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerun: Option<RerunPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validator: Option<Program>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub solution: Option<Program>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Most reruns a policy may ask for, so a slow submission cannot hold a worker for long.
+pub const MAX_RERUNS: u64 = 5;
+
+/// Reruns a test up to `max_reruns` times when it ends within `margin` (a fraction of the time
+/// limit) of the limit, either as Time Limit Exceeded or as a pass.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct RerunPolicy {
+    pub margin: f64,
+    pub max_reruns: u64,
+}
+
+/// A source file in the task directory, compiled with one of the configured languages.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Program {
+    pub file: String,
+    pub language: String,
+}
+
+/// Where the checker lives and how its answer is read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckerType {
+    /// A checker in `checker/` that prints "Correct" and the score.
+    #[default]
+    Builtin,
+    /// A testlib checker in the task directory that accepts with exit code 0.
+    Testlib,
+    /// A Kattis output validator in the task directory. It gets the input, the answer and a
+    /// feedback directory, reads the output on stdin and accepts with exit code 42.
+    Kattis,
+    /// A CMS comparator in the task directory. It gets the input, the answer and the output
+    /// and prints a score between 0 and 1, where only 1 accepts.
+    Cms,
+}
+
+pub fn checker_path(task_dir: &Path, checker: &str, checker_type: CheckerType) -> PathBuf {
+    match checker_type {
+        CheckerType::Builtin => {
+            settings().checker_dir.join(checker)
+        }
+        _ => task_dir.join(checker),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JudgeMode {
+    /// Partial scoring by test or by subtask.
+    #[default]
+    Ioi,
+    /// Judging stops at the first failing test and the score is all or nothing.
+    Icpc,
+}
+
+/// How much of the judge result contestants get to see.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackLevel {
+    /// Every test with its verdict, time and memory.
+    #[default]
+    Full,
+    /// Only the score and verdict of each subtask.
+    SubtaskSummary,
+    /// Only the first failing test and its verdict.
+    FirstFailure,
+    /// Nothing beyond the overall status and score.
+    None,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Subtask {
     pub full_score: u64,
     pub num_testcases: u64,
+    #[serde(default)]
+    pub sample: bool,
+    #[serde(flatten)]
+    pub limits: LimitOverride,
+}
+
+/// Optional limits that replace the task-wide ones for a subtask or a single test.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct LimitOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_limit: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_limit: Option<u64>,
+}
+
+impl TaskConfig {
+    /// Resolves the limits for a test: per-test overrides win over the subtask, which wins
+    /// over the task. Memory and output limits are given in MB in the manifest.
+    pub fn limits(&self, subtask: Option<&Subtask>, test_index: u64) -> Limits {
+        let mut time_limit = self.time_limit;
+        let mut memory_limit = self.memory_limit;
+        let mut output_limit = self.output_limit;
+
+        let overrides = subtask
+            .map(|subtask| &subtask.limits)
+            .into_iter()
+            .chain(self.test_limits.get(&test_index));
+        for limits in overrides {
+            time_limit = limits.time_limit.unwrap_or(time_limit);
+            memory_limit = limits.memory_limit.unwrap_or(memory_limit);
+            output_limit = limits.output_limit.or(output_limit);
+        }
+
+        Limits {
+            time_limit,
+            memory_limit: memory_limit * 1000,
+            output_limit: output_limit.map(|limit| limit * 1000),
+        }
+    }
+
+    /// A test is a sample when it is listed in `samples` or belongs to a sample subtask.
+    pub fn is_sample(&self, test_index: u64) -> bool {
+        self.samples.contains(&test_index) ||
+            self.subtask_of(test_index).is_some_and(|(_, subtask)| subtask.sample)
+    }
+
+    pub fn counts_score(&self, test_index: u64) -> bool {
+        !(self.exclude_sample_score && self.is_sample(test_index))
+    }
+
+    /// Returns the 1-based index of the subtask containing a test, if the task has subtasks.
+    pub fn subtask_of(&self, test_index: u64) -> Option<(u64, &Subtask)> {
+        let mut last_test = 0;
+        for (subtask_index, subtask) in (1..).zip(&self.subtasks) {
+            last_test += subtask.num_testcases;
+            if test_index <= last_test {
+                return Some((subtask_index, subtask));
+            }
+        }
+        None
+    }
 }
 
-pub fn get_config() -> Result<Config> {
+/// Reads, parses and validates config.json without touching the loaded one.
+pub fn load_config() -> Result<Config> {
     let current_dir = env::current_dir()?;
     let config_path = current_dir.join("config.json");
     let config_data = fs::read_to_string(config_path)?;
     let config = serde_json::from_str(&config_data)?;
 
+    let problems = validate_config(&config);
+    if !problems.is_empty() {
+        return Err(anyhow!("config.json is invalid: {}", problems.join(", ")));
+    }
+
     Ok(config)
 }
 
+/// Returns the loaded config, loading it on first use.
+pub fn get_config() -> Result<Arc<Config>> {
+    if let Some(config) = CONFIG.read().unwrap().as_ref() {
+        return Ok(config.clone());
+    }
+
+    let mut loaded = CONFIG.write().unwrap();
+    if loaded.is_none() {
+        *loaded = Some(Arc::new(load_config()?));
+    }
+    Ok(loaded.as_ref().unwrap().clone())
+}
+
+/// Loads config.json again and swaps it in. On error the current config stays. Returns the
+/// changes to the languages, which are logged too.
+pub fn reload_config() -> Result<Vec<String>> {
+    let config = Arc::new(load_config()?);
+
+    let mut loaded = CONFIG.write().unwrap();
+    let changes = match loaded.as_ref() {
+        Some(old_config) => diff_config(old_config, &config),
+        None => vec![],
+    };
+    *loaded = Some(config);
+    drop(loaded);
+
+    if changes.is_empty() {
+        info!(" [x] Reloaded config.json, no language changed");
+    }
+    for change in &changes {
+        info!(" [x] Reloaded config.json, {}", change);
+    }
+
+    Ok(changes)
+}
+
+fn diff_config(old_config: &Config, new_config: &Config) -> Vec<String> {
+    let mut languages: Vec<&String> = old_config.language
+        .keys()
+        .chain(new_config.language.keys())
+        .collect();
+    languages.sort();
+    languages.dedup();
+
+    let mut changes = vec![];
+    for language in languages {
+        match (old_config.language.get(language), new_config.language.get(language)) {
+            (None, Some(_)) => changes.push(format!("language {} added", language)),
+            (Some(_), None) => changes.push(format!("language {} removed", language)),
+            (Some(old), Some(new)) => {
+                let fields = [
+                    ("ext", &old.ext, &new.ext),
+                    ("compile", &old.compile, &new.compile),
+                    ("run", &old.run, &new.run),
+                ];
+                for (field, old, new) in fields {
+                    if old != new {
+                        changes.push(
+                            format!("language {} {}: {:?} -> {:?}", language, field, old, new)
+                        );
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    changes
+}
+
 pub fn get_language_config(language: &str) -> Result<LanguageConfig, Box<dyn Error>> {
     let config = get_config()?;
-    let language_config = config.language;
-
-    match language_config.get(language) {
-        Some(config) =>
-            Ok(LanguageConfig {
-                ext: config.ext.clone(),
-                compile: config.compile.clone(),
-                run: config.run.clone(),
-            }),
+
+    match config.language.get(language) {
+        Some(language_config) => Ok(language_config.clone()),
         None => Err("Unsupported language".into()),
     }
 }
 
+/// The directory of a task, under the tasks directory of the settings.
+pub fn task_dir(task_id: &str) -> PathBuf {
+    settings().tasks_dir.join(task_id)
+}
+
 pub fn get_task_config(task_id: String) -> Result<TaskConfig> {
-    let current_dir = env::current_dir()?;
-    let task_config_path = current_dir.join("tasks").join(task_id).join("manifest.json");
+    let task_config_path = task_dir(&task_id).join("manifest.json");
     let task_config_data = fs::read_to_string(task_config_path)?;
-    let task_config = serde_json::from_str(&task_config_data)?;
 
-    Ok(task_config)
+    parse_task_config(&task_config_data)
+}
+
+/// Parses a manifest of any known version, upgrading it in memory first.
+pub fn parse_task_config(task_config_data: &str) -> Result<TaskConfig> {
+    let mut manifest: Value = serde_json::from_str(task_config_data)?;
+    migrate_manifest(&mut manifest)?;
+
+    Ok(serde_json::from_value(manifest)?)
+}
+
+/// Upgrades a manifest to `MANIFEST_VERSION` one version at a time and returns the version it
+/// started from. Fields the steps do not touch are kept as they are.
+pub fn migrate_manifest(manifest: &mut Value) -> Result<u64> {
+    let object = manifest.as_object_mut().ok_or(anyhow!("manifest must be a JSON object"))?;
+    let from = match object.get("version") {
+        Some(version) => {
+            version
+                .as_u64()
+                .filter(|&version| version > 0)
+                .ok_or(anyhow!("version must be a positive integer"))?
+        }
+        None => default_manifest_version(),
+    };
+    if from > MANIFEST_VERSION {
+        return Err(anyhow!("manifest version {} is newer than {}", from, MANIFEST_VERSION));
+    }
+
+    for version in from..MANIFEST_VERSION {
+        if version == 1 {
+            // Version 1 required `skip` and `subtasks`. They are optional from version 2 on,
+            // so spell out their values for older judges reading the upgraded file.
+            object.entry("skip").or_insert(json!(false));
+            object.entry("subtasks").or_insert(json!([]));
+        }
+    }
+    object.insert("version".to_string(), json!(MANIFEST_VERSION));
+
+    Ok(from)
+}
+
+/// Rewrites `tasks/<task_id>/manifest.json` in the current format. Returns the version the
+/// file had before.
+pub fn migrate_task_config(task_id: &str) -> Result<u64> {
+    let lock = task_lock(task_id);
+    let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let task_config_path = task_dir(task_id).join("manifest.json");
+    let mut manifest: Value = serde_json::from_str(&fs::read_to_string(&task_config_path)?)?;
+
+    let from = migrate_manifest(&mut manifest)?;
+    serde_json::from_value::<TaskConfig>(manifest.clone())?;
+    if from != MANIFEST_VERSION {
+        write_atomic(&task_config_path, serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    }
+
+    Ok(from)
+}
+
+pub fn save_task_config(task_id: &str, task_config: &TaskConfig) -> Result<()> {
+    let task_config_path = task_dir(task_id).join("manifest.json");
+    write_atomic(&task_config_path, serde_json::to_string_pretty(task_config)?.as_bytes())
+}
+
+/// The lock held while a task's files are changed.
+pub(crate) fn task_lock(task_id: &str) -> Arc<Mutex<()>> {
+    let mut locks = TASK_LOCKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    locks.entry(task_id.to_string()).or_default().clone()
+}
+
+/// Changes a task's manifest while holding the task's lock, so concurrent changes are not
+/// lost.
+pub fn update_task_config<T>(
+    task_id: &str,
+    update: impl FnOnce(&mut TaskConfig) -> Result<T>
+) -> Result<T> {
+    let lock = task_lock(task_id);
+    let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let mut task_config = get_task_config(task_id.to_string())?;
+    let value = update(&mut task_config)?;
+    save_task_config(task_id, &task_config)?;
+
+    Ok(value)
+}
+
+/// Writes a file through a temporary file in the same directory, so readers see either the
+/// old or the new contents and never a half-written file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path.file_name().ok_or(anyhow!("{} is not a file", path.display()))?;
+    let temp_path = path.with_file_name(
+        format!(".{}.{}.tmp", file_name.to_string_lossy(), Uuid::new_v4())
+    );
+
+    fs::write(&temp_path, contents)?;
+    if let Err(err) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+pub fn read_task_program(task_id: &str, program: &Program) -> Result<String> {
+    let program_path = task_dir(task_id).join(&program.file);
+
+    Ok(fs::read_to_string(program_path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(time_limit: Option<f64>, memory_limit: Option<u64>) -> LimitOverride {
+        LimitOverride { time_limit, memory_limit, output_limit: None }
+    }
+
+    fn task_config() -> TaskConfig {
+        TaskConfig {
+            time_limit: 1.0,
+            memory_limit: 256,
+            output_limit: Some(64),
+            num_testcases: 3,
+            subtasks: vec![Subtask {
+                full_score: 100,
+                num_testcases: 3,
+                limits: limited(Some(2.0), Some(512)),
+                ..Default::default()
+            }],
+            test_limits: HashMap::from([(3, limited(Some(3.0), None))]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn limits_convert_megabytes_to_kilobytes() {
+        let limits = task_config().limits(None, 1);
+
+        assert_eq!(
+            limits,
+            Limits { time_limit: 1.0, memory_limit: 256_000, output_limit: Some(64_000) }
+        );
+    }
+
+    #[test]
+    fn limits_take_the_subtask_over_the_task() {
+        let task_config = task_config();
+        let limits = task_config.limits(task_config.subtasks.first(), 1);
+
+        assert_eq!(
+            limits,
+            Limits { time_limit: 2.0, memory_limit: 512_000, output_limit: Some(64_000) }
+        );
+    }
+
+    #[test]
+    fn limits_take_the_test_over_the_subtask() {
+        let task_config = task_config();
+        let limits = task_config.limits(task_config.subtasks.first(), 3);
+
+        assert_eq!(
+            limits,
+            Limits { time_limit: 3.0, memory_limit: 512_000, output_limit: Some(64_000) }
+        );
+    }
+
+    #[test]
+    fn limits_apply_test_overrides_without_subtasks() {
+        let task_config = TaskConfig {
+            test_limits: HashMap::from([(2, LimitOverride {
+                output_limit: Some(1),
+                ..Default::default()
+            })]),
+            ..task_config()
+        };
+
+        assert_eq!(task_config.limits(None, 2).output_limit, Some(1000));
+        assert_eq!(task_config.limits(None, 1).output_limit, Some(64_000));
+    }
+
+    #[test]
+    fn migrate_manifest_fills_in_version_1_fields() {
+        let mut manifest = json!({ "time_limit": 1.0 });
+
+        assert_eq!(migrate_manifest(&mut manifest).unwrap(), 1);
+        assert_eq!(manifest["version"], MANIFEST_VERSION);
+        assert_eq!(manifest["skip"], false);
+        assert_eq!(manifest["subtasks"], json!([]));
+    }
+
+    #[test]
+    fn migrate_manifest_keeps_current_manifests() {
+        let mut manifest = json!({ "version": MANIFEST_VERSION, "time_limit": 1.0 });
+
+        assert_eq!(migrate_manifest(&mut manifest).unwrap(), MANIFEST_VERSION);
+        assert_eq!(manifest, json!({ "version": MANIFEST_VERSION, "time_limit": 1.0 }));
+    }
+
+    #[test]
+    fn migrate_manifest_rejects_bad_versions() {
+        for version in [json!(0), json!(-1), json!("2"), json!(MANIFEST_VERSION + 1)] {
+            let mut manifest = json!({ "version": version });
+            assert!(migrate_manifest(&mut manifest).is_err(), "version {}", version);
+        }
+        assert!(migrate_manifest(&mut json!([])).is_err());
+    }
 }
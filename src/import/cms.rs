@@ -0,0 +1,415 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use anyhow::{ anyhow, Result };
+use serde_yaml::Value;
+
+use crate::helper::{ CheckerType, Program, Subtask, TaskConfig, MANIFEST_VERSION };
+use crate::import::{ bytes_to_mb, compile_checker, language_of, write_task, ImportedTask };
+
+/// Directories a CMS task keeps its comparator in, the second one in older tasks.
+const CHECKER_DIRS: [&str; 2] = ["check", "cor"];
+const STATEMENT_DIRS: [&str; 2] = ["statement", "testo"];
+
+/// Imports a CMS task in the italy_yaml format. Subtasks come from the `#ST:` lines of
+/// `gen/GEN`, which list tests in the order they are numbered.
+pub fn import(package_dir: &Path, task_dir: &Path) -> Result<Vec<String>> {
+    let task_yaml: Value = serde_yaml::from_str(
+        &fs::read_to_string(package_dir.join("task.yaml"))?
+    )?;
+    let field = |name: &str| task_yaml.get(name);
+
+    for name in ["infile", "outfile"] {
+        if field(name).and_then(Value::as_str).is_some_and(|file| !file.is_empty()) {
+            return Err(anyhow!("Tasks that use {} instead of standard io are not supported", name));
+        }
+    }
+    if field("output_only").and_then(Value::as_bool) == Some(true) {
+        return Err(anyhow!("Output only tasks are not supported"));
+    }
+
+    let mut task = ImportedTask::default();
+    let time_limit = match field("time_limit").and_then(Value::as_f64) {
+        Some(time_limit) => time_limit,
+        None => {
+            task.warnings.push("task.yaml has no time_limit, 1 second is used".to_string());
+            1.0
+        }
+    };
+    // CMS gives the memory limit in MiB.
+    let memory_limit = field("memory_limit")
+        .and_then(Value::as_u64)
+        .map(|memory_limit| bytes_to_mb(memory_limit * 1024 * 1024))
+        .ok_or(anyhow!("task.yaml has no memory_limit"))?;
+    let total_value = field("total_value")
+        .and_then(Value::as_f64)
+        .map(|total_value| total_value.round() as u64)
+        .unwrap_or(100);
+
+    task.task_config = TaskConfig {
+        version: MANIFEST_VERSION,
+        time_limit,
+        memory_limit,
+        checker: "wcmp".to_string(),
+        full_score: total_value,
+        ..Default::default()
+    };
+
+    let num_testcases = match field("n_input").and_then(Value::as_u64) {
+        Some(num_testcases) => num_testcases,
+        None => count_inputs(&package_dir.join("input")),
+    };
+    for test_index in 0..num_testcases {
+        let input = package_dir.join("input").join(format!("input{}.txt", test_index));
+        let answer = package_dir.join("output").join(format!("output{}.txt", test_index));
+        for path in [&input, &answer] {
+            if !path.is_file() {
+                return Err(
+                    anyhow!(
+                        "{} is missing",
+                        path.strip_prefix(package_dir).unwrap_or(path).display()
+                    )
+                );
+            }
+        }
+        task.tests.push((input, answer));
+    }
+    if task.tests.is_empty() {
+        return Err(anyhow!("The task has no tests"));
+    }
+    task.task_config.num_testcases = num_testcases;
+
+    map_subtasks(&mut task, package_dir, field("score_type_parameters"))?;
+    map_public_testcases(&mut task, field("public_testcases"));
+    map_checker(&mut task, package_dir)?;
+    map_assets(&mut task, package_dir)?;
+
+    let cms_checker = task.task_config.checker_type == CheckerType::Cms;
+    let has_source = task.files.iter().any(|(_, name)| name == "checker.cpp");
+    write_task(task_dir, &task, |staging_dir| {
+        if cms_checker && has_source {
+            compile_checker(staging_dir, "checker.cpp", "checker", staging_dir)?;
+        } else if cms_checker {
+            let permissions = fs::Permissions::from_mode(0o755);
+            fs::set_permissions(staging_dir.join("checker"), permissions)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(task.warnings)
+}
+
+/// Reads subtask scores and sizes from `gen/GEN`, or from `score_type_parameters` when GEN
+/// has none. Without either, tests are scored one by one.
+fn map_subtasks(
+    task: &mut ImportedTask,
+    package_dir: &Path,
+    score_type_parameters: Option<&Value>
+) -> Result<()> {
+    let mut subtasks = match fs::read_to_string(package_dir.join("gen").join("GEN")) {
+        Ok(gen) => parse_gen(&gen)?,
+        Err(_) => vec![],
+    };
+    if subtasks.is_empty() {
+        if let Some(Value::Sequence(parameters)) = score_type_parameters {
+            for parameter in parameters {
+                let score = parameter.get(0).and_then(Value::as_f64);
+                let num_testcases = parameter.get(1).and_then(Value::as_u64);
+                match (score, num_testcases) {
+                    (Some(score), Some(num_testcases)) => {
+                        subtasks.push(Subtask {
+                            full_score: score.round() as u64,
+                            num_testcases,
+                            ..Default::default()
+                        });
+                    }
+                    _ => {
+                        task.warnings.push(
+                            "score_type_parameters that match tests by name are not supported"
+                                .to_string()
+                        );
+                        subtasks.clear();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    let num_testcases: u64 = subtasks
+        .iter()
+        .map(|subtask| subtask.num_testcases)
+        .sum();
+    if !subtasks.is_empty() && num_testcases != task.task_config.num_testcases {
+        task.warnings.push(
+            format!(
+                "Subtasks have {} tests but the task has {}, tests are scored one by one",
+                num_testcases,
+                task.task_config.num_testcases
+            )
+        );
+        subtasks.clear();
+    }
+
+    if !subtasks.is_empty() {
+        task.task_config.full_score = subtasks
+            .iter()
+            .map(|subtask| subtask.full_score)
+            .sum();
+        task.task_config.subtasks = subtasks;
+    }
+
+    Ok(())
+}
+
+/// Every `#ST: <score>` line starts a subtask, and every other line that is not a comment
+/// is one test, as is every `#COPY:` line.
+fn parse_gen(gen: &str) -> Result<Vec<Subtask>> {
+    let mut subtasks: Vec<Subtask> = vec![];
+    let mut tests_before = 0;
+
+    for line in gen.lines() {
+        let line = line.trim();
+        let is_test = if let Some(directive) = line.strip_prefix('#') {
+            let directive = directive.trim_start();
+            if let Some(score) = directive.strip_prefix("ST:") {
+                let score: f64 = score.trim().parse()?;
+                subtasks.push(Subtask { full_score: score.round() as u64, ..Default::default() });
+                false
+            } else {
+                directive.starts_with("COPY:")
+            }
+        } else {
+            !line.split('#').next().unwrap_or("").trim().is_empty()
+        };
+
+        if is_test {
+            match subtasks.last_mut() {
+                Some(subtask) => {
+                    subtask.num_testcases += 1;
+                }
+                None => {
+                    tests_before += 1;
+                }
+            }
+        }
+    }
+
+    if !subtasks.is_empty() && tests_before > 0 {
+        subtasks.insert(0, Subtask { num_testcases: tests_before, ..Default::default() });
+    }
+    subtasks.retain(|subtask| subtask.num_testcases > 0);
+
+    Ok(subtasks)
+}
+
+/// `public_testcases` is `all` or a comma separated list of test numbers counted from 0.
+fn map_public_testcases(task: &mut ImportedTask, public_testcases: Option<&Value>) {
+    let public_testcases = match public_testcases {
+        Some(Value::String(list)) => list.clone(),
+        Some(Value::Number(test_index)) => test_index.to_string(),
+        _ => {
+            return;
+        }
+    };
+
+    if public_testcases.trim() == "all" {
+        task.task_config.samples = (1..=task.task_config.num_testcases).collect();
+        return;
+    }
+    for test_index in public_testcases.split(',') {
+        match test_index.trim().parse::<u64>() {
+            Ok(test_index) if test_index < task.task_config.num_testcases => {
+                task.task_config.samples.push(test_index + 1);
+            }
+            _ => task.warnings.push(format!("Public testcase {:?} is ignored", test_index)),
+        }
+    }
+}
+
+/// A comparator is used from source when there is one, or else as the compiled `checker`.
+/// Without a comparator CMS compares outputs token by token, like `wcmp`.
+fn map_checker(task: &mut ImportedTask, package_dir: &Path) -> Result<()> {
+    let Some(checker_dir) = CHECKER_DIRS.iter()
+        .map(|dir| package_dir.join(dir))
+        .find(|dir| dir.is_dir()) else {
+        return Ok(());
+    };
+
+    let mut entries: Vec<_> = fs::read_dir(&checker_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+
+    let source = entries.iter().find(|file| {
+        let stem = file.file_stem().and_then(|stem| stem.to_str());
+        let ext = file.extension().and_then(|ext| ext.to_str());
+        matches!(stem, Some("checker" | "correttore")) && matches!(ext, Some("cpp" | "cc"))
+    });
+    let binary = ["checker", "correttore"]
+        .iter()
+        .map(|name| checker_dir.join(name))
+        .find(|file| file.is_file());
+
+    if let Some(source) = source {
+        for file in &entries {
+            if file.extension().is_some_and(|ext| ext == "h" || ext == "hpp") {
+                let name = file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                task.files.push((file.clone(), name));
+            }
+        }
+        task.files.push((source.clone(), "checker.cpp".to_string()));
+    } else if let Some(binary) = binary {
+        task.files.push((binary, "checker".to_string()));
+    } else {
+        return Err(anyhow!("{} has no checker", checker_dir.display()));
+    }
+    task.task_config.checker = "checker".to_string();
+    task.task_config.checker_type = CheckerType::Cms;
+    task.warnings.push(
+        "The comparator only accepts a test when it gives the full score".to_string()
+    );
+
+    Ok(())
+}
+
+/// Maps the solution in `sol/` and a PDF statement. Tasks with a grader are imported without
+/// it.
+fn map_assets(task: &mut ImportedTask, package_dir: &Path) -> Result<()> {
+    let sol_dir = package_dir.join("sol");
+    if sol_dir.is_dir() {
+        let mut files: Vec<_> = fs::read_dir(&sol_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        files.sort();
+
+        let is_grader = |file: &Path| {
+            file.file_stem().is_some_and(|stem| stem.to_string_lossy().starts_with("grader"))
+        };
+        if files.iter().any(|file| is_grader(file)) {
+            task.warnings.push("Graders are not supported and were left out".to_string());
+        }
+
+        let solution = files.iter().find_map(|file| {
+            if is_grader(file) {
+                return None;
+            }
+            let ext = file.extension()?.to_str()?;
+            language_of(ext).map(|language| (file, language))
+        });
+        if let Some((file, (language, ext))) = solution {
+            let name = format!("solution.{}", ext);
+            task.files.push((file.clone(), name.clone()));
+            task.task_config.solution = Some(Program {
+                file: name,
+                language: language.to_string(),
+            });
+        }
+    }
+
+    let statement = STATEMENT_DIRS.iter()
+        .filter_map(|dir| fs::read_dir(package_dir.join(dir)).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|file| file.extension().is_some_and(|ext| ext == "pdf"));
+    match statement {
+        Some(statement) => {
+            task.statement = Some(statement);
+        }
+        None => task.warnings.push("The task has no PDF statement".to_string()),
+    }
+
+    Ok(())
+}
+
+fn count_inputs(input_dir: &Path) -> u64 {
+    (0..)
+        .take_while(|test_index| input_dir.join(format!("input{}.txt", test_index)).is_file())
+        .count() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn sizes(subtasks: &[Subtask]) -> Vec<(u64, u64)> {
+        subtasks
+            .iter()
+            .map(|subtask| (subtask.full_score, subtask.num_testcases))
+            .collect()
+    }
+
+    fn task_with_tests(num_testcases: u64) -> ImportedTask {
+        let mut task = ImportedTask::default();
+        task.task_config.num_testcases = num_testcases;
+        task
+    }
+
+    #[test]
+    fn parses_subtasks_from_gen() {
+        let gen = "\
+# A comment
+#ST: 0
+#COPY: testo/sample.txt
+#ST: 40
+gen 1 10   # a small test
+gen 2 10
+
+#ST: 60
+gen 3 1000
+#COPY: big.txt
+";
+        assert_eq!(sizes(&parse_gen(gen).unwrap()), vec![(0, 1), (40, 2), (60, 2)]);
+    }
+
+    #[test]
+    fn puts_tests_before_the_first_subtask_in_one_worth_nothing() {
+        let gen = "gen 1\ngen 2\n#ST: 100\ngen 3\n#ST: 0\n";
+        assert_eq!(sizes(&parse_gen(gen).unwrap()), vec![(0, 2), (100, 1)]);
+        assert!(parse_gen("gen 1\ngen 2\n").unwrap().is_empty());
+        assert!(parse_gen("#ST: ten\ngen 1\n").is_err());
+    }
+
+    #[test]
+    fn falls_back_to_score_type_parameters() {
+        let package_dir = PathBuf::from("/nonexistent");
+        let parameters: Value = serde_yaml::from_str("[[30, 2], [70, 3]]").unwrap();
+        let mut task = task_with_tests(5);
+        map_subtasks(&mut task, &package_dir, Some(&parameters)).unwrap();
+        assert_eq!(sizes(&task.task_config.subtasks), vec![(30, 2), (70, 3)]);
+        assert_eq!(task.task_config.full_score, 100);
+
+        let mut task = task_with_tests(4);
+        map_subtasks(&mut task, &package_dir, Some(&parameters)).unwrap();
+        assert!(task.task_config.subtasks.is_empty());
+        assert_eq!(task.warnings.len(), 1);
+
+        let parameters: Value = serde_yaml::from_str("[[30, \"0_.*\"]]").unwrap();
+        let mut task = task_with_tests(5);
+        map_subtasks(&mut task, &package_dir, Some(&parameters)).unwrap();
+        assert!(task.task_config.subtasks.is_empty());
+        assert_eq!(task.warnings.len(), 1);
+    }
+
+    #[test]
+    fn maps_public_testcases_to_samples() {
+        let mut task = task_with_tests(3);
+        map_public_testcases(&mut task, Some(&Value::from("0, 2, 3, x")));
+        assert_eq!(task.task_config.samples, vec![1, 3]);
+        assert_eq!(task.warnings.len(), 2);
+
+        let mut task = task_with_tests(3);
+        map_public_testcases(&mut task, Some(&Value::from("all")));
+        assert_eq!(task.task_config.samples, vec![1, 2, 3]);
+
+        let mut task = task_with_tests(3);
+        map_public_testcases(&mut task, Some(&Value::from(1)));
+        assert_eq!(task.task_config.samples, vec![2]);
+
+        let mut task = task_with_tests(3);
+        map_public_testcases(&mut task, None);
+        assert!(task.task_config.samples.is_empty());
+    }
+}
@@ -0,0 +1,504 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use anyhow::{ anyhow, Result };
+use serde_yaml::{ Mapping, Value };
+
+use crate::helper::{
+    CheckerType,
+    JudgeMode,
+    Program,
+    Subtask,
+    TaskConfig,
+    MANIFEST_VERSION,
+};
+use crate::import::{ compile_checker, language_of, write_task, zip_dir, ImportedTask };
+
+/// Kattis defaults for limits a package leaves out, in MB.
+const DEFAULT_MEMORY_LIMIT: u64 = 2048;
+const DEFAULT_OUTPUT_LIMIT: u64 = 8;
+
+/// Built-in checkers that compare floats, with the tolerance of the default output validator
+/// they match.
+const FLOAT_CHECKERS: [(&str, f64); 3] = [
+    ("rcmp4", 1e-4),
+    ("rcmp6", 1e-6),
+    ("rcmp9", 1e-9),
+];
+
+/// Imports a Kattis problem package. Scoring problems get a subtask per test group of
+/// `data/secret`, and pass-fail problems are judged in ICPC mode.
+pub fn import(package_dir: &Path, task_dir: &Path) -> Result<Vec<String>> {
+    let problem: Value = serde_yaml::from_str(
+        &fs::read_to_string(package_dir.join("problem.yaml"))?
+    )?;
+    let types = problem_types(&problem);
+    if types.iter().any(|problem_type| problem_type == "interactive") {
+        return Err(anyhow!("Interactive problems are not supported"));
+    }
+
+    let mut task = ImportedTask::default();
+    let limits = problem.get("limits");
+    let limit = |name: &str| limits.and_then(|limits| limits.get(name));
+
+    let time_limit = match limit("time_limit").and_then(Value::as_f64) {
+        Some(time_limit) => time_limit,
+        None =>
+            match fs::read_to_string(package_dir.join(".timelimit")) {
+                Ok(time_limit) => time_limit.trim().parse()?,
+                Err(_) => {
+                    task.warnings.push(
+                        "The package has no time limit, 1 second is used".to_string()
+                    );
+                    1.0
+                }
+            }
+    };
+    task.task_config = TaskConfig {
+        version: MANIFEST_VERSION,
+        time_limit,
+        memory_limit: limit("memory").and_then(Value::as_u64).unwrap_or(DEFAULT_MEMORY_LIMIT),
+        output_limit: Some(
+            limit("output").and_then(Value::as_u64).unwrap_or(DEFAULT_OUTPUT_LIMIT)
+        ),
+        checker: "wcmp".to_string(),
+        ..Default::default()
+    };
+
+    let data_dir = package_dir.join("data");
+    let samples = list_tests(&data_dir.join("sample"))?;
+    let secret_dir = data_dir.join("secret");
+    let mut groups = vec![(secret_dir.clone(), list_tests(&secret_dir)?)];
+    for group_dir in list_dirs(&secret_dir)? {
+        let tests = list_tests(&group_dir)?;
+        groups.push((group_dir, tests));
+    }
+    groups.retain(|(_, tests)| !tests.is_empty());
+    if samples.is_empty() && groups.is_empty() {
+        return Err(anyhow!("The package has no tests"));
+    }
+
+    if types.iter().any(|problem_type| problem_type == "scoring") {
+        map_scoring(&mut task, samples, groups);
+    } else {
+        task.task_config.mode = JudgeMode::Icpc;
+        task.task_config.full_score = 100;
+        for test in samples {
+            task.tests.push(test);
+            task.task_config.samples.push(task.tests.len() as u64);
+        }
+        for (_, tests) in groups {
+            task.tests.extend(tests);
+        }
+    }
+    task.task_config.num_testcases = task.tests.len() as u64;
+
+    let validator_flags = problem.get("validator_flags").and_then(Value::as_str).unwrap_or("");
+    let custom = problem
+        .get("validation")
+        .and_then(Value::as_str)
+        .is_some_and(|validation| validation.starts_with("custom"));
+    if custom {
+        map_output_validator(&mut task, package_dir)?;
+    } else {
+        map_validator_flags(&mut task, validator_flags);
+    }
+
+    map_assets(&mut task, package_dir)?;
+
+    let kattis_checker = task.task_config.checker_type == CheckerType::Kattis;
+    write_task(task_dir, &task, |staging_dir| {
+        if kattis_checker {
+            compile_checker(staging_dir, "checker.cpp", "checker", staging_dir)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(task.warnings)
+}
+
+/// `type` is a single word in the legacy format and may be a list in newer ones.
+fn problem_types(problem: &Value) -> Vec<String> {
+    match problem.get("type") {
+        Some(Value::String(problem_type)) => vec![problem_type.clone()],
+        Some(Value::Sequence(types)) =>
+            types
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+        _ => vec![],
+    }
+}
+
+/// Samples become a subtask worth nothing and every test group a subtask worth the top of its
+/// `range` in `testdata.yaml`.
+fn map_scoring(
+    task: &mut ImportedTask,
+    samples: Vec<(PathBuf, PathBuf)>,
+    groups: Vec<(PathBuf, Vec<(PathBuf, PathBuf)>)>
+) {
+    if !samples.is_empty() {
+        task.task_config.subtasks.push(Subtask {
+            full_score: 0,
+            num_testcases: samples.len() as u64,
+            sample: true,
+            ..Default::default()
+        });
+        task.tests.extend(samples);
+    }
+
+    for (group_dir, tests) in groups {
+        let name = group_dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let score = match group_score(&group_dir) {
+            Some(score) => score,
+            None => {
+                task.warnings.push(format!("Group {:?} has no score range, 0 is used", name));
+                0
+            }
+        };
+        task.task_config.subtasks.push(Subtask {
+            full_score: score,
+            num_testcases: tests.len() as u64,
+            ..Default::default()
+        });
+        task.tests.extend(tests);
+    }
+
+    task.task_config.full_score = task.task_config.subtasks
+        .iter()
+        .map(|subtask| subtask.full_score)
+        .sum();
+}
+
+fn group_score(group_dir: &Path) -> Option<u64> {
+    let testdata = fs::read_to_string(group_dir.join("testdata.yaml")).ok()?;
+    let testdata: Value = serde_yaml::from_str(&testdata).ok()?;
+    let range = testdata.get("range")?.as_str()?;
+    let max: f64 = range.split_whitespace().nth(1)?.parse().ok()?;
+    Some(max.round() as u64)
+}
+
+/// The default output validator compares tokens, so it maps to `wcmp`, or to a float checker
+/// when it has a tolerance.
+fn map_validator_flags(task: &mut ImportedTask, validator_flags: &str) {
+    let flags: Vec<&str> = validator_flags.split_whitespace().collect();
+    let mut index = 0;
+    while index < flags.len() {
+        match flags[index] {
+            "float_tolerance" | "float_absolute_tolerance" | "float_relative_tolerance" => {
+                let tolerance: f64 = flags
+                    .get(index + 1)
+                    .and_then(|tolerance| tolerance.parse().ok())
+                    .unwrap_or(1e-6);
+                let found = FLOAT_CHECKERS.iter()
+                    .rev()
+                    .find(|(_, checker_tolerance)| *checker_tolerance >= tolerance);
+                let (checker, _) = found.unwrap_or_else(|| {
+                    // The loosest checker is still stricter than asked, but closest to it.
+                    task.warnings.push(
+                        format!("Tolerance {} is looser than any checker, using rcmp4", tolerance)
+                    );
+                    &FLOAT_CHECKERS[0]
+                });
+                task.task_config.checker = checker.to_string();
+                index += 2;
+            }
+            flag => {
+                task.warnings.push(format!("Validator flag {:?} is ignored", flag));
+                index += 1;
+            }
+        }
+    }
+}
+
+fn map_output_validator(task: &mut ImportedTask, package_dir: &Path) -> Result<()> {
+    let validator_dir = list_dirs(&package_dir.join("output_validators"))?
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("The package has custom validation but no output validator"))?;
+
+    let mut source = None;
+    for file in list_files(&validator_dir)? {
+        let name = file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        match file.extension().and_then(|ext| ext.to_str()) {
+            Some("cpp" | "cc" | "cxx") => {
+                source = Some(file);
+            }
+            Some("h" | "hpp") => task.files.push((file, name)),
+            _ => {}
+        }
+    }
+
+    let source = source.ok_or(anyhow!("Only C++ output validators are supported"))?;
+    task.files.push((source, "checker.cpp".to_string()));
+    task.task_config.checker = "checker".to_string();
+    task.task_config.checker_type = CheckerType::Kattis;
+
+    Ok(())
+}
+
+/// Maps the first accepted submission and a PDF statement. Input validators exit with 42
+/// instead of 0 and are left out.
+fn map_assets(task: &mut ImportedTask, package_dir: &Path) -> Result<()> {
+    let accepted = list_files(&package_dir.join("submissions").join("accepted"))?;
+    let solution = accepted.iter().find_map(|file| {
+        let ext = file.extension()?.to_str()?;
+        language_of(ext).map(|language| (file, language))
+    });
+    if let Some((file, (language, ext))) = solution {
+        let name = format!("solution.{}", ext);
+        task.files.push((file.clone(), name.clone()));
+        task.task_config.solution = Some(Program { file: name, language: language.to_string() });
+    }
+
+    if package_dir.join("input_validators").is_dir() {
+        task.warnings.push("Input validators are not imported".to_string());
+    }
+
+    let statements = list_files(&package_dir.join("problem_statement"))?;
+    let statement = statements
+        .iter()
+        .filter(|file| file.extension().is_some_and(|ext| ext == "pdf"))
+        .min_by_key(|file| !file.to_string_lossy().contains(".en."));
+    match statement {
+        Some(statement) => {
+            task.statement = Some(statement.clone());
+        }
+        None => task.warnings.push("The package has no PDF statement".to_string()),
+    }
+
+    Ok(())
+}
+
+/// Exports a task as a Kattis problem package zip at `zip_path`, using `work_dir` to lay it
+/// out. Returns what could not be carried over exactly.
+pub fn export(
+    task_id: &str,
+    task_dir: &Path,
+    task_config: &TaskConfig,
+    work_dir: &Path,
+    zip_path: &Path
+) -> Result<Vec<String>> {
+    let mut warnings = vec![];
+    if work_dir.exists() {
+        fs::remove_dir_all(work_dir)?;
+    }
+    let sample_dir = work_dir.join("data").join("sample");
+    let secret_dir = work_dir.join("data").join("secret");
+    fs::create_dir_all(&sample_dir)?;
+    fs::create_dir_all(&secret_dir)?;
+
+    let scoring = task_config.mode == JudgeMode::Ioi;
+    let mut problem = Mapping::new();
+    problem.insert("name".into(), task_id.into());
+    problem.insert("type".into(), (if scoring { "scoring" } else { "pass-fail" }).into());
+
+    let mut limits = Mapping::new();
+    limits.insert("time_limit".into(), task_config.time_limit.into());
+    limits.insert("memory".into(), task_config.memory_limit.into());
+    if let Some(output_limit) = task_config.output_limit {
+        limits.insert("output".into(), output_limit.into());
+    }
+    problem.insert("limits".into(), limits.into());
+    fs::write(work_dir.join(".timelimit"), task_config.time_limit.to_string())?;
+
+    let has_overrides =
+        !task_config.test_limits.is_empty() ||
+        task_config.subtasks.iter().any(|subtask| subtask.limits != Default::default());
+    if has_overrides {
+        warnings.push("Limits of single subtasks and tests are left out".to_string());
+    }
+
+    let testcases_dir = task_dir.join("testcases");
+    let copy_test = |test_index: u64, dir: &Path| -> Result<()> {
+        fs::create_dir_all(dir)?;
+        let name = format!("{:03}", test_index);
+        fs::copy(
+            testcases_dir.join(format!("{}.in", test_index)),
+            dir.join(format!("{}.in", name))
+        )?;
+        fs::copy(
+            testcases_dir.join(format!("{}.sol", test_index)),
+            dir.join(format!("{}.ans", name))
+        )?;
+        Ok(())
+    };
+
+    for test_index in 1..=task_config.num_testcases {
+        if task_config.is_sample(test_index) {
+            copy_test(test_index, &sample_dir)?;
+        }
+    }
+
+    if !scoring {
+        for test_index in 1..=task_config.num_testcases {
+            if !task_config.is_sample(test_index) {
+                copy_test(test_index, &secret_dir)?;
+            }
+        }
+    } else if task_config.subtasks.is_empty() {
+        // Every test is worth an equal share of the score and scores add up.
+        let scored: Vec<u64> = (1..=task_config.num_testcases)
+            .filter(|&test_index| task_config.counts_score(test_index))
+            .collect();
+        for &test_index in &scored {
+            copy_test(test_index, &secret_dir)?;
+        }
+        let test_score = (task_config.full_score as f64) / (scored.len().max(1) as f64);
+        write_testdata(&secret_dir, test_score, "sum", task_config.full_score)?;
+    } else {
+        // A test group per subtask, worth its score only when every test passes.
+        let mut first_test = 1;
+        let mut num_groups = 0;
+        for subtask in &task_config.subtasks {
+            let test_indexes = first_test..first_test + subtask.num_testcases;
+            first_test += subtask.num_testcases;
+            if subtask.sample && subtask.full_score == 0 {
+                continue;
+            }
+
+            num_groups += 1;
+            let group_dir = secret_dir.join(format!("group{}", num_groups));
+            for test_index in test_indexes {
+                copy_test(test_index, &group_dir)?;
+            }
+            let score = subtask.full_score;
+            write_testdata(&group_dir, score as f64, "min", score)?;
+        }
+    }
+
+    match task_config.checker_type {
+        CheckerType::Builtin => {
+            let float = FLOAT_CHECKERS.iter().find(|(checker, _)| *checker == task_config.checker);
+            if let Some((_, tolerance)) = float {
+                problem.insert(
+                    "validator_flags".into(),
+                    format!("float_tolerance {}", tolerance).into()
+                );
+            } else if !["wcmp", "lcmp", "ncmp", "fcmp", "hcmp"].contains(
+                &task_config.checker.as_str()
+            ) {
+                warnings.push(
+                    format!("Checker {:?} is exported as token comparison", task_config.checker)
+                );
+            }
+        }
+        CheckerType::Kattis => {
+            problem.insert("validation".into(), "custom".into());
+            let validator_dir = work_dir.join("output_validators").join("checker");
+            fs::create_dir_all(&validator_dir)?;
+            for file in list_files(task_dir)? {
+                let is_source = file.file_name().is_some_and(|name| name == "checker.cpp");
+                let is_header = file.extension().is_some_and(|ext| ext == "h" || ext == "hpp");
+                if is_source || is_header {
+                    fs::copy(&file, validator_dir.join(file.file_name().unwrap_or_default()))?;
+                }
+            }
+        }
+        _ => {
+            warnings.push(
+                "The checker has another interface and is exported as token comparison".to_string()
+            );
+        }
+    }
+
+    if let Some(solution) = &task_config.solution {
+        let accepted_dir = work_dir.join("submissions").join("accepted");
+        fs::create_dir_all(&accepted_dir)?;
+        fs::copy(task_dir.join(&solution.file), accepted_dir.join(&solution.file))?;
+    }
+    if task_config.validator.is_some() {
+        warnings.push("The validator is left out".to_string());
+    }
+
+    let statement = task_dir.join("desc.pdf");
+    if statement.is_file() {
+        let statement_dir = work_dir.join("problem_statement");
+        fs::create_dir_all(&statement_dir)?;
+        fs::copy(statement, statement_dir.join("problem.pdf"))?;
+    }
+
+    fs::write(work_dir.join("problem.yaml"), serde_yaml::to_string(&problem)?)?;
+    zip_dir(work_dir, zip_path)?;
+    fs::remove_dir_all(work_dir)?;
+
+    Ok(warnings)
+}
+
+fn write_testdata(group_dir: &Path, accept_score: f64, grader: &str, max: u64) -> Result<()> {
+    let mut testdata = Mapping::new();
+    testdata.insert("on_reject".into(), "continue".into());
+    testdata.insert("accept_score".into(), accept_score.into());
+    testdata.insert("grader_flags".into(), grader.into());
+    testdata.insert("range".into(), format!("0 {}", max).into());
+    fs::write(group_dir.join("testdata.yaml"), serde_yaml::to_string(&testdata)?)?;
+
+    Ok(())
+}
+
+/// Pairs of `.in` and `.ans` files directly in `dir`, sorted by name.
+fn list_tests(dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    Ok(
+        list_files(dir)?
+            .into_iter()
+            .filter(|file| file.extension().is_some_and(|ext| ext == "in"))
+            .map(|input| {
+                let answer = input.with_extension("ans");
+                (input, answer)
+            })
+            .filter(|(_, answer)| answer.is_file())
+            .collect()
+    )
+}
+
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    list_entries(dir, false)
+}
+
+fn list_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    list_entries(dir, true)
+}
+
+fn list_entries(dir: &Path, dirs: bool) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.retain(|path| path.is_dir() == dirs);
+    entries.sort();
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker_for(validator_flags: &str) -> (String, Vec<String>) {
+        let mut task = ImportedTask::default();
+        map_validator_flags(&mut task, validator_flags);
+        (task.task_config.checker, task.warnings)
+    }
+
+    #[test]
+    fn picks_the_strictest_checker_within_the_tolerance() {
+        assert_eq!(checker_for("float_tolerance 1e-6"), ("rcmp6".to_string(), vec![]));
+        assert_eq!(checker_for("float_relative_tolerance 1e-9"), ("rcmp9".to_string(), vec![]));
+        assert_eq!(checker_for("float_absolute_tolerance 1e-5"), ("rcmp4".to_string(), vec![]));
+    }
+
+    #[test]
+    fn falls_back_to_the_loosest_checker_with_a_warning() {
+        let (checker, warnings) = checker_for("float_tolerance 0.01");
+        assert_eq!(checker, "rcmp4");
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn warns_about_unknown_flags() {
+        let (_, warnings) = checker_for("case_sensitive space_change_sensitive");
+        assert_eq!(warnings.len(), 2);
+    }
+}
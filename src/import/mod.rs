@@ -0,0 +1,285 @@
+use std::fs::{ self, File };
+use std::io::{ Cursor, Write };
+use std::path::{ Path, PathBuf };
+use anyhow::{ anyhow, Result };
+use uuid::Uuid;
+use zip::{ write::FileOptions, ZipWriter };
+
+use crate::helper::{ get_language_config, get_task_config, task_dir, task_lock, TaskConfig };
+
+pub mod cms;
+pub mod kattis;
+pub mod polygon;
+
+/// A task read from another package format, ready to be written in this judge's layout.
+#[derive(Debug, Default)]
+pub struct ImportedTask {
+    pub task_config: TaskConfig,
+    /// Input and answer of every test. They are numbered from 1 in this order.
+    pub tests: Vec<(PathBuf, PathBuf)>,
+    pub statement: Option<PathBuf>,
+    /// Files copied into the task directory as they are, with their new name.
+    pub files: Vec<(PathBuf, String)>,
+    /// Things that could not be carried over exactly.
+    pub warnings: Vec<String>,
+}
+
+/// Extracts a package uploaded as a zip and imports it into `task_dir`. Returns the warnings
+/// of the import.
+pub fn import_package(format: &str, data: &[u8], task_dir: &Path) -> Result<Vec<String>> {
+    let package_dir = task_dir.join(".import");
+    if package_dir.exists() {
+        fs::remove_dir_all(&package_dir)?;
+    }
+    let warnings = zip_extract::extract(Cursor::new(data), &package_dir, true)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| import_dir(format, &package_dir, task_dir));
+
+    if package_dir.exists() {
+        fs::remove_dir_all(&package_dir)?;
+    }
+    warnings
+}
+
+/// Imports a package that is already extracted into `package_dir`.
+pub fn import_dir(format: &str, package_dir: &Path, task_dir: &Path) -> Result<Vec<String>> {
+    match format {
+        "polygon" => polygon::import(package_dir, task_dir),
+        "kattis" => kattis::import(package_dir, task_dir),
+        "cms" => cms::import(package_dir, task_dir),
+        _ => Err(anyhow!("Unknown package format {:?}", format)),
+    }
+}
+
+/// Imports a package from a zip or a directory on disk into `tasks/<task_id>`, for
+/// `judge-ma-di import <format> <path> <task_id>`.
+pub fn import_path(format: &str, path: &Path, task_id: &str) -> Result<Vec<String>> {
+    let task_dir = task_dir(task_id);
+    fs::create_dir_all(&task_dir)?;
+
+    if path.is_dir() {
+        import_dir(format, path, &task_dir)
+    } else {
+        import_package(format, &fs::read(path)?, &task_dir)
+    }
+}
+
+/// Exports a task as a package zip in the given format. Returns the zip and what could not be
+/// carried over exactly.
+pub fn export_package(format: &str, task_id: &str) -> Result<(Vec<u8>, Vec<String>)> {
+    let task_config = get_task_config(task_id.to_string())?;
+    let task_dir = task_dir(task_id);
+    // A directory of its own, so exports of the same task can run side by side.
+    let export_dir = std::env::temp_dir().join(format!("judge-export-{}", Uuid::new_v4()));
+    let work_dir = export_dir.join("package");
+    let zip_path = export_dir.join("package.zip");
+
+    let exported = match format {
+        "kattis" => kattis::export(task_id, &task_dir, &task_config, &work_dir, &zip_path),
+        _ => Err(anyhow!("Unknown package format {:?}", format)),
+    };
+    let exported = exported.and_then(|warnings| Ok((fs::read(&zip_path)?, warnings)));
+
+    if export_dir.exists() {
+        fs::remove_dir_all(&export_dir)?;
+    }
+    exported
+}
+
+/// Replaces the tests, statement and manifest in `task_dir` with the imported ones. Everything
+/// is written to a staging directory first and `build` runs there, e.g. to compile the
+/// checker. Only when all of it succeeds are the files moved into `task_dir`, so a failed
+/// import leaves the old task as it was. The task's lock is held throughout, so the swap
+/// does not race another import or a manifest update.
+pub fn write_task(
+    task_dir: &Path,
+    task: &ImportedTask,
+    build: impl FnOnce(&Path) -> Result<()>
+) -> Result<()> {
+    let task_id = task_dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let lock = task_lock(&task_id);
+    let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let staging_dir = task_dir.join(".staging");
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)?;
+    }
+
+    let staged = stage_task(&staging_dir, task).and_then(|()| build(&staging_dir));
+    let written = staged.and_then(|()| swap_in(&staging_dir, task_dir));
+
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)?;
+    }
+    written
+}
+
+fn stage_task(staging_dir: &Path, task: &ImportedTask) -> Result<()> {
+    let testcases_dir = staging_dir.join("testcases");
+    fs::create_dir_all(&testcases_dir)?;
+
+    for (test_index, (input, answer)) in (1..).zip(&task.tests) {
+        fs::copy(input, testcases_dir.join(format!("{}.in", test_index)))?;
+        fs::copy(answer, testcases_dir.join(format!("{}.sol", test_index)))?;
+    }
+    zip_dir(&testcases_dir, &staging_dir.join("testcases.zip"))?;
+
+    if let Some(statement) = &task.statement {
+        fs::copy(statement, staging_dir.join("desc.pdf"))?;
+    }
+    for (source, name) in &task.files {
+        fs::copy(source, staging_dir.join(name))?;
+    }
+
+    let manifest = serde_json::to_string_pretty(&task.task_config)?;
+    fs::write(staging_dir.join("manifest.json"), manifest)?;
+
+    Ok(())
+}
+
+/// Moves every staged entry into `task_dir`, replacing what is there. The old entry is renamed
+/// aside first and only removed once the new one is in place, so a failed rename puts it back.
+/// The manifest goes last so the task is never described by a manifest newer than its tests.
+fn swap_in(staging_dir: &Path, task_dir: &Path) -> Result<()> {
+    let mut names = fs::read_dir(staging_dir)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    names.sort_by_key(|name| name == "manifest.json");
+
+    for name in names {
+        let target = task_dir.join(&name);
+        let aside = task_dir.join(format!(".old-{}", name.to_string_lossy()));
+        remove_entry(&aside)?;
+
+        let moved_aside = target.exists();
+        if moved_aside {
+            fs::rename(&target, &aside)?;
+        }
+        if let Err(err) = fs::rename(staging_dir.join(&name), &target) {
+            if moved_aside {
+                fs::rename(&aside, &target)?;
+            }
+            return Err(err.into());
+        }
+        remove_entry(&aside)?;
+    }
+
+    Ok(())
+}
+
+fn remove_entry(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the files of a directory, recursively, into a zip archive.
+pub fn zip_dir(dir: &Path, zip_path: &Path) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(zip_path)?);
+    add_dir_to_zip(&mut zip, dir, "")?;
+    zip.finish()?;
+
+    Ok(())
+}
+
+fn add_dir_to_zip(zip: &mut ZipWriter<File>, dir: &Path, prefix: &str) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        if path.is_dir() {
+            zip.add_directory(format!("{}/", name), FileOptions::default())?;
+            add_dir_to_zip(zip, &path, &format!("{}/", name))?;
+        } else {
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(&fs::read(&path)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts a memory limit in bytes to the MB, of 1000 KB, that the judge's limits are in.
+pub fn bytes_to_mb(bytes: u64) -> u64 {
+    bytes / 1_000_000
+}
+
+/// Maps a source file extension to a language in config.json and the extension it is stored
+/// with.
+pub fn language_of(ext: &str) -> Option<(&'static str, &'static str)> {
+    let language = match ext {
+        "cpp" | "cc" | "cxx" => ("cpp", "cpp"),
+        "c" => ("c", "c"),
+        "py" => ("python", "py"),
+        _ => {
+            return None;
+        }
+    };
+
+    get_language_config(language.0).ok().map(|_| language)
+}
+
+/// Compiles a testlib checker that was copied into the task directory.
+pub fn compile_checker(
+    task_dir: &Path,
+    source: &str,
+    output: &str,
+    include_dir: &Path
+) -> Result<()> {
+    let result = std::process::Command::new("g++")
+        .arg("-std=c++17")
+        .arg("-O2")
+        .arg(task_dir.join(source))
+        .arg("-o")
+        .arg(task_dir.join(output))
+        .arg("-I")
+        .arg(include_dir)
+        .output()?;
+
+    if !result.status.success() {
+        return Err(
+            anyhow!("Checker failed to compile: {}", String::from_utf8_lossy(&result.stderr))
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swaps_staged_entries_in_and_removes_the_old_ones() {
+        let dir = std::env::temp_dir().join(format!("import-swap-{}", Uuid::new_v4()));
+        let (staging_dir, task_dir) = (dir.join("staging"), dir.join("task"));
+        fs::create_dir_all(staging_dir.join("testcases")).unwrap();
+        fs::create_dir_all(task_dir.join("testcases")).unwrap();
+        fs::write(staging_dir.join("testcases").join("1.in"), "new").unwrap();
+        fs::write(staging_dir.join("manifest.json"), "new").unwrap();
+        fs::write(task_dir.join("testcases").join("2.in"), "old").unwrap();
+        fs::write(task_dir.join("manifest.json"), "old").unwrap();
+        fs::write(task_dir.join("checker"), "kept").unwrap();
+
+        let swapped = swap_in(&staging_dir, &task_dir);
+        let mut names: Vec<_> = fs::read_dir(&task_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        let tests = fs::read_dir(task_dir.join("testcases")).unwrap().count();
+        let manifest = fs::read_to_string(task_dir.join("manifest.json")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        swapped.unwrap();
+        assert_eq!(names, vec!["checker", "manifest.json", "testcases"]);
+        assert_eq!(tests, 1);
+        assert_eq!(manifest, "new");
+    }
+}
@@ -0,0 +1,452 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use anyhow::{ anyhow, Result };
+use roxmltree::{ Document, Node };
+
+use crate::helper::{ CheckerType, JudgeMode, Program, Subtask, TaskConfig, MANIFEST_VERSION };
+use crate::import::{ bytes_to_mb, compile_checker, language_of, write_task, ImportedTask };
+
+struct PolygonTest {
+    input: PathBuf,
+    answer: PathBuf,
+    points: Option<f64>,
+    group: Option<String>,
+    sample: bool,
+}
+
+struct PolygonGroup {
+    name: String,
+    points: Option<f64>,
+    each_test: bool,
+}
+
+/// Imports a Codeforces Polygon package, as extracted from its zip. Tests are regrouped so
+/// each group becomes one subtask, since subtasks here are ranges of consecutive tests.
+pub fn import(package_dir: &Path, task_dir: &Path) -> Result<Vec<String>> {
+    let xml = fs::read_to_string(package_dir.join("problem.xml"))?;
+    let document = Document::parse(&xml)?;
+    let root = document.root_element();
+
+    let testset = root
+        .descendants()
+        .find(|node| node.has_tag_name("testset") && node.attribute("name") == Some("tests"))
+        .ok_or(anyhow!("problem.xml has no \"tests\" testset"))?;
+
+    let time_limit: f64 = child_text(testset, "time-limit")?.parse()?;
+    let memory_limit: u64 = child_text(testset, "memory-limit")?.parse()?;
+    let input_pattern = child_text(testset, "input-path-pattern")?;
+    let answer_pattern = child_text(testset, "answer-path-pattern")?;
+
+    let mut task = ImportedTask {
+        task_config: TaskConfig {
+            version: MANIFEST_VERSION,
+            time_limit: time_limit / 1000.0,
+            memory_limit: bytes_to_mb(memory_limit),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let mut tests = vec![];
+    let test_nodes: Vec<Node> = child(testset, "tests")
+        .map(|tests| tests.children().filter(|node| node.has_tag_name("test")).collect())
+        .unwrap_or_default();
+    for (polygon_index, node) in (1..).zip(test_nodes) {
+        let input = package_dir.join(format_pattern(input_pattern, polygon_index));
+        let answer = package_dir.join(format_pattern(answer_pattern, polygon_index));
+        for path in [&input, &answer] {
+            if !path.is_file() {
+                return Err(
+                    anyhow!(
+                        "{} is missing, export the package with generated tests",
+                        path.strip_prefix(package_dir).unwrap_or(path).display()
+                    )
+                );
+            }
+        }
+
+        tests.push(PolygonTest {
+            input,
+            answer,
+            points: node.attribute("points").map(str::parse).transpose()?,
+            group: node.attribute("group").map(str::to_string),
+            sample: node.attribute("sample") == Some("true"),
+        });
+    }
+    if tests.is_empty() {
+        return Err(anyhow!("The package has no tests"));
+    }
+
+    let mut groups: Vec<PolygonGroup> = vec![];
+    if let Some(group_nodes) = child(testset, "groups") {
+        for node in group_nodes.children().filter(|node| node.has_tag_name("group")) {
+            groups.push(PolygonGroup {
+                name: node.attribute("name").unwrap_or_default().to_string(),
+                points: node.attribute("points").map(str::parse).transpose()?,
+                each_test: node.attribute("points-policy") == Some("each-test"),
+            });
+        }
+    }
+    for test in &tests {
+        if let Some(group) = &test.group {
+            if !groups.iter().any(|known| &known.name == group) {
+                groups.push(PolygonGroup { name: group.clone(), points: None, each_test: false });
+            }
+        }
+    }
+
+    if !groups.is_empty() {
+        map_groups(&mut task, tests, &groups);
+    } else {
+        map_tests(&mut task, tests);
+    }
+
+    map_assets(&mut task, root, package_dir)?;
+
+    let testlib_checker = task.task_config.checker_type == CheckerType::Testlib;
+    write_task(task_dir, &task, |staging_dir| {
+        if testlib_checker {
+            compile_checker(staging_dir, "checker.cpp", "checker", &package_dir.join("files"))?;
+        }
+        Ok(())
+    })?;
+
+    Ok(task.warnings)
+}
+
+/// Each group becomes a subtask worth the group's points, or the sum of its tests' points.
+/// Tests outside every group come first, as a subtask of their own.
+fn map_groups(task: &mut ImportedTask, tests: Vec<PolygonTest>, groups: &[PolygonGroup]) {
+    let ungrouped = PolygonGroup { name: String::new(), points: None, each_test: false };
+    let mut tests: Vec<Option<PolygonTest>> = tests.into_iter().map(Some).collect();
+
+    for group in std::iter::once(&ungrouped).chain(groups) {
+        let group_tests: Vec<PolygonTest> = tests
+            .iter_mut()
+            .filter(|test| {
+                test.as_ref().is_some_and(|test| test.group.as_deref().unwrap_or("") == group.name)
+            })
+            .filter_map(Option::take)
+            .collect();
+        if group_tests.is_empty() {
+            continue;
+        }
+
+        let points = group.points.unwrap_or_else(|| {
+            group_tests
+                .iter()
+                .filter_map(|test| test.points)
+                .sum()
+        });
+        if group.each_test && points > 0.0 {
+            task.warnings.push(
+                format!(
+                    "Group {:?} scores each test in Polygon but is all or nothing here",
+                    group.name
+                )
+            );
+        }
+
+        task.task_config.subtasks.push(Subtask {
+            full_score: points_to_score(points, &mut task.warnings),
+            num_testcases: group_tests.len() as u64,
+            sample: group_tests.iter().all(|test| test.sample),
+            ..Default::default()
+        });
+        for test in group_tests {
+            add_test(task, test);
+        }
+    }
+
+    task.task_config.full_score = task.task_config.subtasks
+        .iter()
+        .map(|subtask| subtask.full_score)
+        .sum();
+}
+
+/// Without groups, tests with equal points are scored one by one. Unequal points need a
+/// subtask per test, and a package without points is judged ICPC style.
+fn map_tests(task: &mut ImportedTask, tests: Vec<PolygonTest>) {
+    let points: Vec<f64> = tests
+        .iter()
+        .map(|test| test.points.unwrap_or(0.0))
+        .collect();
+    let total: f64 = points.iter().sum();
+
+    if total == 0.0 {
+        task.task_config.mode = JudgeMode::Icpc;
+        task.task_config.full_score = 100;
+    } else {
+        task.task_config.full_score = points_to_score(total, &mut task.warnings);
+        if points.iter().any(|&test_points| test_points != points[0]) {
+            for &test_points in &points {
+                task.task_config.subtasks.push(Subtask {
+                    full_score: points_to_score(test_points, &mut task.warnings),
+                    num_testcases: 1,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    for test in tests {
+        add_test(task, test);
+    }
+}
+
+fn add_test(task: &mut ImportedTask, test: PolygonTest) {
+    task.tests.push((test.input, test.answer));
+    if test.sample {
+        task.task_config.samples.push(task.tests.len() as u64);
+    }
+    task.task_config.num_testcases = task.tests.len() as u64;
+}
+
+fn points_to_score(points: f64, warnings: &mut Vec<String>) -> u64 {
+    if points.fract() != 0.0 {
+        warnings.push(format!("Points {} were rounded to a whole number", points));
+    }
+    points.round() as u64
+}
+
+/// Maps the checker, validator, main solution and PDF statement.
+fn map_assets(task: &mut ImportedTask, root: Node, package_dir: &Path) -> Result<()> {
+    let checker = root
+        .descendants()
+        .find(|node| node.has_tag_name("checker"))
+        .ok_or(anyhow!("problem.xml has no checker"))?;
+    match checker.attribute("name").and_then(|name| name.strip_prefix("std::")) {
+        Some(name) => {
+            task.task_config.checker = name.trim_end_matches(".cpp").to_string();
+        }
+        None => {
+            let source = source_path(checker).ok_or(anyhow!("The checker has no source"))?;
+            task.files.push((package_dir.join(source), "checker.cpp".to_string()));
+            task.task_config.checker = "checker".to_string();
+            task.task_config.checker_type = CheckerType::Testlib;
+        }
+    }
+
+    let testlib = package_dir.join("files").join("testlib.h");
+    if testlib.is_file() {
+        task.files.push((testlib, "testlib.h".to_string()));
+    }
+
+    let validator = root.descendants().find(|node| node.has_tag_name("validator"));
+    if let Some(validator) = validator {
+        task.task_config.validator = map_program(task, validator, package_dir, "validator");
+    }
+    let solution = root
+        .descendants()
+        .find(|node| node.has_tag_name("solution") && node.attribute("tag") == Some("main"));
+    if let Some(solution) = solution {
+        task.task_config.solution = map_program(task, solution, package_dir, "solution");
+    }
+
+    let statements: Vec<Node> = root
+        .descendants()
+        .filter(|node| {
+            node.has_tag_name("statement") && node.attribute("type") == Some("application/pdf")
+        })
+        .collect();
+    let statement = statements
+        .iter()
+        .find(|node| node.attribute("language") == Some("english"))
+        .or(statements.first());
+    match statement.and_then(|node| node.attribute("path")) {
+        Some(path) => {
+            task.statement = Some(package_dir.join(path));
+        }
+        None => task.warnings.push("The package has no PDF statement".to_string()),
+    }
+
+    Ok(())
+}
+
+fn map_program(
+    task: &mut ImportedTask,
+    node: Node,
+    package_dir: &Path,
+    name: &str
+) -> Option<Program> {
+    let source = source_path(node)?;
+    let ext = Path::new(source).extension()?.to_string_lossy().into_owned();
+    match language_of(&ext) {
+        Some((language, ext)) => {
+            let file = format!("{}.{}", name, ext);
+            task.files.push((package_dir.join(source), file.clone()));
+            Some(Program { file, language: language.to_string() })
+        }
+        None => {
+            task.warnings.push(format!("The {} {:?} is in an unsupported language", name, source));
+            None
+        }
+    }
+}
+
+fn source_path<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    child(node, "source")?.attribute("path")
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Result<&'a str> {
+    child(node, name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+        .ok_or(anyhow!("problem.xml has no {}", name))
+}
+
+/// Fills in a Polygon path pattern such as `tests/%02d`.
+fn format_pattern(pattern: &str, index: u64) -> String {
+    let Some(start) = pattern.find('%') else {
+        return pattern.to_string();
+    };
+    let Some(end) = pattern[start..].find('d').map(|end| start + end) else {
+        return pattern.to_string();
+    };
+    let width: usize = pattern[start + 1..end].parse().unwrap_or(0);
+
+    format!("{}{:0width$}{}", &pattern[..start], index, &pattern[end + 1..], width = width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(points: Option<f64>, group: Option<&str>, sample: bool) -> PolygonTest {
+        PolygonTest {
+            input: PathBuf::from("in"),
+            answer: PathBuf::from("ans"),
+            points,
+            group: group.map(str::to_string),
+            sample,
+        }
+    }
+
+    fn group(name: &str, points: Option<f64>) -> PolygonGroup {
+        PolygonGroup { name: name.to_string(), points, each_test: false }
+    }
+
+    fn subtasks(task: &ImportedTask) -> Vec<(u64, u64, bool)> {
+        task.task_config.subtasks
+            .iter()
+            .map(|subtask| (subtask.full_score, subtask.num_testcases, subtask.sample))
+            .collect()
+    }
+
+    #[test]
+    fn maps_each_group_to_a_subtask_with_ungrouped_tests_first() {
+        let mut task = ImportedTask::default();
+        let tests = vec![
+            test(None, Some("samples"), true),
+            test(Some(10.0), Some("first"), false),
+            test(None, None, false),
+            test(Some(15.0), Some("first"), false),
+            test(None, Some("second"), false),
+        ];
+        let groups = [
+            group("samples", Some(0.0)),
+            group("first", None),
+            group("second", Some(75.0)),
+        ];
+        map_groups(&mut task, tests, &groups);
+
+        assert_eq!(
+            subtasks(&task),
+            vec![(0, 1, false), (0, 1, true), (25, 2, false), (75, 1, false)]
+        );
+        assert_eq!(task.task_config.full_score, 100);
+        assert_eq!(task.task_config.num_testcases, 5);
+        assert_eq!(task.task_config.samples, vec![2]);
+    }
+
+    #[test]
+    fn warns_about_groups_scored_per_test() {
+        let mut task = ImportedTask::default();
+        let groups = [PolygonGroup { name: "all".to_string(), points: None, each_test: true }];
+        map_groups(&mut task, vec![test(Some(50.5), Some("all"), false)], &groups);
+
+        assert_eq!(subtasks(&task), vec![(51, 1, false)]);
+        assert_eq!(task.warnings.len(), 2);
+    }
+
+    #[test]
+    fn scores_ungrouped_tests_with_equal_points_one_by_one() {
+        let mut task = ImportedTask::default();
+        map_tests(&mut task, vec![test(Some(20.0), None, true), test(Some(20.0), None, false)]);
+
+        assert!(task.task_config.subtasks.is_empty());
+        assert_eq!(task.task_config.mode, JudgeMode::default());
+        assert_eq!(task.task_config.full_score, 40);
+        assert_eq!(task.task_config.samples, vec![1]);
+    }
+
+    #[test]
+    fn gives_ungrouped_tests_with_unequal_points_a_subtask_each() {
+        let mut task = ImportedTask::default();
+        map_tests(&mut task, vec![test(Some(30.0), None, false), test(Some(70.0), None, false)]);
+
+        assert_eq!(subtasks(&task), vec![(30, 1, false), (70, 1, false)]);
+        assert_eq!(task.task_config.full_score, 100);
+    }
+
+    #[test]
+    fn judges_ungrouped_tests_without_points_icpc_style() {
+        let mut task = ImportedTask::default();
+        map_tests(&mut task, vec![test(None, None, false), test(None, None, false)]);
+
+        assert_eq!(task.task_config.mode, JudgeMode::Icpc);
+        assert_eq!(task.task_config.full_score, 100);
+        assert_eq!(task.task_config.num_testcases, 2);
+    }
+
+    fn map_checker(checker: &str) -> ImportedTask {
+        let xml = format!("<problem><assets>{}</assets></problem>", checker);
+        let document = Document::parse(&xml).unwrap();
+        let mut task = ImportedTask::default();
+        map_assets(&mut task, document.root_element(), Path::new("/nonexistent")).unwrap();
+        task
+    }
+
+    #[test]
+    fn maps_standard_checkers_to_the_built_in_ones() {
+        let task = map_checker(
+            r#"<checker name="std::rcmp6.cpp" type="testlib">
+                <source path="files/check.cpp" type="cpp.g++17"/>
+            </checker>"#
+        );
+
+        assert_eq!(task.task_config.checker, "rcmp6");
+        assert_eq!(task.task_config.checker_type, CheckerType::default());
+        assert!(task.files.is_empty());
+    }
+
+    #[test]
+    fn compiles_other_checkers_with_testlib() {
+        let task = map_checker(
+            r#"<checker type="testlib"><source path="files/check.cpp" type="cpp.g++17"/></checker>"#
+        );
+
+        assert_eq!(task.task_config.checker, "checker");
+        assert_eq!(task.task_config.checker_type, CheckerType::Testlib);
+        assert_eq!(
+            task.files,
+            vec![(PathBuf::from("/nonexistent/files/check.cpp"), "checker.cpp".to_string())]
+        );
+    }
+
+    #[test]
+    fn converts_the_memory_limit_from_bytes() {
+        assert_eq!(bytes_to_mb(256 * 1024 * 1024), 268);
+        assert_eq!(bytes_to_mb(256_000_000), 256);
+    }
+
+    #[test]
+    fn formats_path_patterns() {
+        assert_eq!(format_pattern("tests/%02d", 7), "tests/07");
+        assert_eq!(format_pattern("tests/%d.a", 12), "tests/12.a");
+    }
+}
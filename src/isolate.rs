@@ -1,37 +1,52 @@
-use std::{ fs::{ self, File }, io::Write, path::PathBuf, str::from_utf8 };
+use std::{ fs::{ self, File }, io::{ Read, Write }, path::PathBuf, str::from_utf8 };
 use tokio::process::Command;
-use std::env;
 use anyhow::Result;
 
-#[derive(Debug, PartialEq)]
+use crate::helper::{ checker_path, task_dir, CheckerType };
+
+#[derive(Debug, Default, PartialEq)]
 pub enum RunVerdict {
     CompilationError,
+    #[default]
     VerdictOK,
     VerdictTLE,
     VerdictMLE,
+    VerdictOLE,
     VerdictRE,
     VerdictXX,
     VerdictSG,
 }
 
-impl Default for RunVerdict {
-    fn default() -> Self {
-        Self::VerdictOK
-    }
+/// isolate only has boxes 0 to 999 by default. Submissions use the ones below `JOB_BOX_START`
+/// and each job worker gets `BOXES_PER_JOB` consecutive boxes above it, up to `CLI_BOX_ID`.
+pub const JOB_BOX_START: u64 = 900;
+pub const BOXES_PER_JOB: u64 = 3;
+pub const MAX_JOB_WORKER: u64 = (CLI_BOX_ID - JOB_BOX_START) / BOXES_PER_JOB;
+/// The last box, kept free of workers so `judge-ma-di judge` can run next to a server.
+pub const CLI_BOX_ID: u64 = 999;
+
+/// Signal the kernel sends a process that writes past `--fsize`.
+const SIGXFSZ: &str = "25";
+
+/// Resource limits for a single run. Memory and output limits are in KB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub time_limit: f64,
+    pub memory_limit: u64,
+    pub output_limit: Option<u64>,
 }
 
 #[derive(Default, Debug)]
 pub struct Isolate {
     pub box_path: PathBuf,
     pub box_id: u64,
-    pub time_limit: f64,
-    pub memory_limit: u64,
     pub task_id: String,
     pub code: String,
     pub ext: String,
     pub compile_script: String,
     pub run_script: String,
     pub checker: String,
+    pub checker_type: CheckerType,
 }
 
 #[derive(Default, PartialEq, Debug)]
@@ -39,6 +54,7 @@ pub struct IsolateResult {
     pub status: RunVerdict,
     pub time_usage: f64,
     pub memory_usage: u64,
+    pub message: String,
 }
 
 impl Isolate {
@@ -52,12 +68,15 @@ impl Isolate {
         let box_path = String::from_utf8(box_path.stdout)?;
         self.box_path = PathBuf::from(box_path.trim()).join("box");
 
-        let current_dir = env::current_dir()?;
         let destination_path = self.box_path.join(format!("source.{}", self.ext));
         let mut file = File::create(destination_path)?;
         file.write_all(self.code.as_bytes())?;
 
-        let input_path = current_dir.join("tasks").join(&self.task_id).join("testcases");
+        Ok(())
+    }
+
+    pub async fn copy_testcases(&mut self) -> Result<()> {
+        let input_path = task_dir(&self.task_id).join("testcases");
 
         for entry in fs::read_dir(input_path)? {
             let entry = entry?;
@@ -70,6 +89,18 @@ impl Isolate {
         Ok(())
     }
 
+    /// Copies the files with the given extension from the task directory into the box.
+    pub fn copy_task_files(&self, ext: &str) -> Result<()> {
+        for entry in fs::read_dir(task_dir(&self.task_id))? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|file_ext| file_ext == ext) {
+                fs::copy(&path, self.box_path.join(path.file_name().unwrap_or_default()))?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn compile(&mut self) -> Result<IsolateResult> {
         let mut compile_script = self.compile_script.replace(
             "{source_file}",
@@ -90,54 +121,124 @@ impl Isolate {
         let mut result: IsolateResult = Default::default();
         if !output.status.success() {
             result.status = RunVerdict::CompilationError;
+            result.message = String::from_utf8_lossy(&output.stderr).into_owned();
         }
 
         Ok(result)
     }
 
     pub async fn check(&mut self, test_index: u64) -> Result<bool> {
-        let current_dir = env::current_dir()?;
-        let checker_dir = current_dir.join("checker");
+        self.check_files(&format!("{}.in", test_index), &format!("{}.sol", test_index)).await
+    }
 
-        let result = Command::new(format!("{}/{}", checker_dir.display(), self.checker))
-            .arg(format!("{}/{}.in", self.box_path.display(), test_index))
-            .arg(format!("{}/out.out", self.box_path.display()))
-            .arg(format!("{}/{}.sol", self.box_path.display(), test_index))
-            .output().await?;
+    /// Runs the checker on `out.out` against an input and an answer file in the box.
+    pub async fn check_files(&mut self, input: &str, answer: &str) -> Result<bool> {
+        let checker = checker_path(&task_dir(&self.task_id), &self.checker, self.checker_type);
 
-        let stdout = from_utf8(&result.stdout).unwrap().to_string();
+        let input = format!("{}/{}", self.box_path.display(), input);
+        let output = format!("{}/out.out", self.box_path.display());
+        let answer = format!("{}/{}", self.box_path.display(), answer);
 
-        Ok(stdout == "Correct\n100\n".to_string())
-    }
+        if self.checker_type == CheckerType::Kattis {
+            let feedback_dir = self.box_path.join("feedback");
+            fs::create_dir_all(&feedback_dir)?;
+            let result = Command::new(checker)
+                .arg(input)
+                .arg(answer)
+                .arg(format!("{}/", feedback_dir.display()))
+                .stdin(File::open(output)?)
+                .output().await?;
+            return Ok(result.status.code() == Some(42));
+        }
 
-    pub async fn run(&mut self, test_index: u64) -> Result<IsolateResult> {
-        let run_script = self.run_script.replace("{source}", "source");
-        let split: Vec<&str> = run_script.split(' ').collect();
+        if self.checker_type == CheckerType::Cms {
+            let result = Command::new(checker).arg(input).arg(answer).arg(output).output().await?;
+            let score = from_utf8(&result.stdout)?
+                .split_whitespace()
+                .next()
+                .and_then(|score| score.parse::<f64>().ok());
+            return Ok(score.is_some_and(|score| score >= 1.0));
+        }
 
-        Command::new("isolate")
+        let result = Command::new(checker).arg(input).arg(output).arg(answer).output().await?;
+
+        match self.checker_type {
+            CheckerType::Builtin => {
+                let stdout = from_utf8(&result.stdout).unwrap().to_string();
+                Ok(stdout == "Correct\n100\n")
+            }
+            _ => Ok(result.status.success()),
+        }
+    }
+
+    fn command(&self, limits: &Limits, stdin: &str) -> Command {
+        let mut command = Command::new("isolate");
+        command
             .arg("--cg")
             .arg(format!("--box-id={}", self.box_id))
-            .arg(format!("--time={}", self.time_limit.to_string()))
-            .arg(format!("--wall-time={}", (self.time_limit + 5.0).to_string()))
-            .arg(format!("--extra-time={}", (self.time_limit + 1.0).to_string()))
-            .arg(format!("--cg-mem={}", self.memory_limit))
+            .arg(format!("--time={}", limits.time_limit))
+            .arg(format!("--wall-time={}", limits.time_limit + 5.0))
+            .arg(format!("--extra-time={}", limits.time_limit + 1.0))
+            .arg(format!("--cg-mem={}", limits.memory_limit))
             .arg(format!("--meta={}/meta.txt", self.box_path.display()))
-            .arg(format!("--stdin={}.in", test_index))
-            .arg("--stdout=out.out")
+            .arg(format!("--stdin={}", stdin))
+            .arg("--stdout=out.out");
+        if let Some(output_limit) = limits.output_limit {
+            command.arg(format!("--fsize={}", output_limit));
+        }
+        command
+    }
+
+    pub async fn run(&mut self, test_index: u64, limits: &Limits) -> Result<IsolateResult> {
+        let run_script = self.run_script.replace("{source}", "source");
+        let split: Vec<&str> = run_script.split(' ').collect();
+
+        self.command(limits, &format!("{}.in", test_index))
             // .arg("--processes=128")
             .arg("--run")
             .arg("--")
             .args(split)
             .output().await?;
 
-        let result = self.get_result().await?;
+        let result = self.get_result(limits).await?;
 
         Ok(result)
     }
 
-    pub async fn get_result(&self) -> Result<IsolateResult> {
+    /// Runs the program with extra arguments on a file already in the box, keeping stderr in
+    /// `err.out` next to the usual `out.out`.
+    pub async fn run_with_input(
+        &mut self,
+        stdin: &str,
+        args: &[String],
+        limits: &Limits
+    ) -> Result<IsolateResult> {
+        let run_script = self.run_script.replace("{source}", "source");
+        let split: Vec<&str> = run_script.split(' ').collect();
+
+        self.command(limits, stdin)
+            .arg("--stderr=err.out")
+            .arg("--run")
+            .arg("--")
+            .args(split)
+            .args(args)
+            .output().await?;
+
+        let result = self.get_result(limits).await?;
+
+        Ok(result)
+    }
+
+    pub fn write_box_file(&self, name: &str, contents: &str) -> Result<()> {
+        fs::write(self.box_path.join(name), contents)?;
+
+        Ok(())
+    }
+
+    pub async fn get_result(&self, limits: &Limits) -> Result<IsolateResult> {
         let mut result: IsolateResult = Default::default();
         let mut memory_limit_exceeded = false;
+        let mut exit_signal = None;
 
         let meta = fs::read_to_string(format!("{}/meta.txt", self.box_path.display()))?;
 
@@ -163,17 +264,58 @@ impl Isolate {
                     "cg-oom-killed" => {
                         memory_limit_exceeded = args[1].trim() == "1";
                     }
+                    "exitsig" => {
+                        exit_signal = Some(args[1].trim().to_string());
+                    }
                     _ => (),
                 }
             }
         }
-        if memory_limit_exceeded || result.memory_usage >= self.memory_limit {
+        if memory_limit_exceeded || result.memory_usage >= limits.memory_limit {
             result.status = RunVerdict::VerdictMLE;
+        } else if self.exceeded_output_limit(&result.status, exit_signal.as_deref(), limits) {
+            result.status = RunVerdict::VerdictOLE;
         }
 
         Ok(result)
     }
 
+    /// A program killed by SIGXFSZ wrote past the output limit. One that ignores the signal
+    /// gets a write error instead and usually exits with an error once its output is full.
+    fn exceeded_output_limit(
+        &self,
+        status: &RunVerdict,
+        exit_signal: Option<&str>,
+        limits: &Limits
+    ) -> bool {
+        let Some(output_limit) = limits.output_limit else {
+            return false;
+        };
+        match status {
+            RunVerdict::VerdictSG => exit_signal == Some(SIGXFSZ),
+            RunVerdict::VerdictRE => {
+                fs::metadata(self.box_path.join("out.out"))
+                    .is_ok_and(|metadata| metadata.len() >= output_limit * 1024)
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads at most `limit` bytes of a file in the box, marking the text when it was cut.
+    pub fn read_box_file(&self, name: &str, limit: u64) -> Result<String> {
+        let mut data = vec![];
+        File::open(self.box_path.join(name))?.take(limit + 1).read_to_end(&mut data)?;
+
+        let truncated = data.len() as u64 > limit;
+        data.truncate(limit as usize);
+        let mut text = String::from_utf8_lossy(&data).into_owned();
+        if truncated {
+            text.push_str("...");
+        }
+
+        Ok(text)
+    }
+
     pub async fn cleanup(&mut self) -> Result<()> {
         Command::new("isolate")
             .arg("--cg")
@@ -0,0 +1,659 @@
+use std::{ fs, path::PathBuf };
+use std::time::{ Duration, Instant };
+use anyhow::{ anyhow, Result };
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+use crate::helper::{
+    get_language_config,
+    get_task_config,
+    read_task_program,
+    task_dir,
+    update_task_config,
+    Program,
+    TaskConfig,
+};
+use crate::isolate::{ Isolate, Limits, RunVerdict };
+use crate::runner::Verdict;
+
+/// Work that is not judging a submission. Jobs go through their own queue and worker pool,
+/// and their result is sent back to whoever published them.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Job {
+    Run(RunJob),
+    Challenge(ChallengeJob),
+    Stress(StressJob),
+    Calibrate(CalibrateJob),
+}
+
+/// What a job worker sends back: the job's output, or why it failed.
+pub type JobReply = std::result::Result<Value, String>;
+
+/// Compiles and runs code once on the given stdin, without a task or a checker.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RunJob {
+    pub code: String,
+    pub language: String,
+    #[serde(default)]
+    pub stdin: String,
+    pub time_limit: Option<f64>,
+    pub memory_limit: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RunOutput {
+    pub status: String,
+    pub verdict: Verdict,
+    pub compile_output: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub time: f64,
+    pub memory: u64,
+}
+
+/// Judges a submission on an input given by another participant. The input must pass the
+/// task's validator, and the task's model solution produces the expected answer.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChallengeJob {
+    pub task_id: String,
+    pub code: String,
+    pub language: String,
+    pub input: String,
+    #[serde(default)]
+    pub add_to_tests: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChallengeOutput {
+    pub successful: bool,
+    pub status: String,
+    pub verdict: Option<Verdict>,
+    pub message: String,
+    pub added_test: Option<u64>,
+}
+
+/// Code sent along with a job.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Source {
+    pub code: String,
+    pub language: String,
+}
+
+/// Runs a generator with increasing seeds and compares a candidate solution with a reference
+/// solution through the task's checker until they disagree.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StressJob {
+    pub task_id: String,
+    pub generator: Source,
+    pub reference: Source,
+    pub candidate: Source,
+    #[serde(default = "default_stress_iterations")]
+    pub iterations: u64,
+    #[serde(default = "default_stress_seed")]
+    pub seed: u64,
+}
+
+fn default_stress_iterations() -> u64 {
+    100
+}
+
+fn default_stress_seed() -> u64 {
+    1
+}
+
+pub const MAX_STRESS_ITERATIONS: u64 = 1000;
+/// Wall-clock time after which a stress job stops starting new iterations. One iteration runs
+/// three programs of at most `MAX_TIME_LIMIT` each, so callers should wait a bit longer.
+pub const STRESS_TIME_BUDGET: Duration = Duration::from_secs(480);
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StressOutput {
+    pub found: bool,
+    pub iterations: u64,
+    /// The time budget ran out before all iterations were done.
+    pub timed_out: bool,
+    pub seed: Option<u64>,
+    pub verdict: Option<Verdict>,
+    pub input: String,
+    pub reference_output: String,
+    pub candidate_output: String,
+}
+
+/// Runs the task's model solution on every test several times and suggests a time limit of
+/// `factor` times the worst time seen.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CalibrateJob {
+    #[serde(default)]
+    pub task_id: String,
+    #[serde(default = "default_calibration_runs")]
+    pub runs: u64,
+    #[serde(default = "default_calibration_factor")]
+    pub factor: f64,
+    #[serde(default)]
+    pub write: bool,
+}
+
+fn default_calibration_runs() -> u64 {
+    3
+}
+
+fn default_calibration_factor() -> f64 {
+    2.0
+}
+
+pub const MAX_CALIBRATION_RUNS: u64 = 10;
+/// Wall-clock time after which a calibration stops measuring, so it cannot hold a job worker
+/// for long. A run takes at most `MAX_TIME_LIMIT` plus isolate's extra time.
+pub const CALIBRATION_TIME_BUDGET: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Measurement {
+    pub test_index: u64,
+    pub verdict: Verdict,
+    pub times: Vec<f64>,
+    pub memory: u64,
+}
+
+/// Also stored as `calibration.json` in the task directory.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CalibrateOutput {
+    pub runs: u64,
+    pub factor: f64,
+    pub worst_time: f64,
+    pub suggested_time_limit: f64,
+    pub failed_tests: Vec<u64>,
+    /// The time budget ran out before every test was measured.
+    pub timed_out: bool,
+    pub written: bool,
+    /// Why the suggested limit was not written although `write` was set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_written: Option<String>,
+    pub measurements: Vec<Measurement>,
+}
+
+pub const DEFAULT_TIME_LIMIT: f64 = 1.0;
+pub const MAX_TIME_LIMIT: f64 = 10.0;
+pub const DEFAULT_MEMORY_LIMIT: u64 = 256;
+pub const MAX_MEMORY_LIMIT: u64 = 1024;
+const OUTPUT_LIMIT: u64 = 64;
+const SHOWN_OUTPUT_LIMIT: u64 = 64 * 1024;
+/// Outputs that are used as answers are kept whole, up to the output limit.
+const FULL_OUTPUT_LIMIT: u64 = OUTPUT_LIMIT * 1024 * 1024;
+
+pub async fn execute(job: Job, box_id: u64) -> Result<Value> {
+    match job {
+        Job::Run(job) => Ok(serde_json::to_value(run_code(job, box_id).await?)?),
+        Job::Challenge(job) => Ok(serde_json::to_value(challenge(job, box_id).await?)?),
+        Job::Stress(job) => Ok(serde_json::to_value(stress(job, box_id).await?)?),
+        Job::Calibrate(job) => Ok(serde_json::to_value(calibrate(job, box_id).await?)?),
+    }
+}
+
+fn new_isolate(box_id: u64, code: String, language: &str) -> Result<Isolate> {
+    let language_config = get_language_config(language).map_err(|err| anyhow!("{}", err))?;
+
+    Ok(Isolate {
+        box_path: PathBuf::new(),
+        box_id,
+        code,
+        ext: language_config.ext,
+        compile_script: language_config.compile,
+        run_script: language_config.run,
+        ..Default::default()
+    })
+}
+
+fn use_task_checker(isolate: &mut Isolate, task_id: &str, task_config: &TaskConfig) {
+    isolate.task_id = task_id.to_string();
+    isolate.checker = task_config.checker.clone();
+    isolate.checker_type = task_config.checker_type;
+}
+
+fn tool_limits() -> Limits {
+    Limits {
+        time_limit: MAX_TIME_LIMIT,
+        memory_limit: MAX_MEMORY_LIMIT * 1000,
+        output_limit: Some(OUTPUT_LIMIT * 1000),
+    }
+}
+
+async fn run_code(job: RunJob, box_id: u64) -> Result<RunOutput> {
+    let memory_limit = job.memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT).min(MAX_MEMORY_LIMIT);
+    let limits = Limits {
+        time_limit: job.time_limit.unwrap_or(DEFAULT_TIME_LIMIT).min(MAX_TIME_LIMIT),
+        memory_limit: memory_limit * 1000,
+        output_limit: Some(OUTPUT_LIMIT * 1000),
+    };
+
+    let isolate = new_isolate(box_id, job.code, &job.language)?;
+    run_program(isolate, &job.stdin, &limits, SHOWN_OUTPUT_LIMIT).await
+}
+
+/// A program of the task such as its validator. Headers in the task directory, like
+/// `testlib.h`, are copied next to the source when the box is set up.
+fn new_task_isolate(box_id: u64, task_id: &str, program: &Program) -> Result<Isolate> {
+    let code = read_task_program(task_id, program)?;
+    let mut isolate = new_isolate(box_id, code, &program.language)?;
+    isolate.task_id = task_id.to_string();
+
+    Ok(isolate)
+}
+
+async fn init_box(isolate: &mut Isolate) -> Result<()> {
+    isolate.init().await?;
+    if !isolate.task_id.is_empty() {
+        isolate.copy_task_files("h")?;
+    }
+
+    Ok(())
+}
+
+/// Compiles and runs a program once in a fresh box, keeping up to `shown_limit` bytes of
+/// its stdout and stderr.
+async fn run_program(
+    mut isolate: Isolate,
+    stdin: &str,
+    limits: &Limits,
+    shown_limit: u64
+) -> Result<RunOutput> {
+    // The box is cleaned up even when setting it up failed half way.
+    let output = match init_box(&mut isolate).await {
+        Ok(()) => run_in_box(&mut isolate, stdin, limits, shown_limit).await,
+        Err(err) => Err(err),
+    };
+    isolate.cleanup().await?;
+
+    output
+}
+
+async fn run_in_box(
+    isolate: &mut Isolate,
+    stdin: &str,
+    limits: &Limits,
+    shown_limit: u64
+) -> Result<RunOutput> {
+    let compile_result = isolate.compile().await?;
+    if compile_result.status == RunVerdict::CompilationError {
+        return Ok(RunOutput {
+            status: Verdict::CompilationError.description().to_string(),
+            verdict: Verdict::CompilationError,
+            compile_output: compile_result.message,
+            stdout: String::new(),
+            stderr: String::new(),
+            time: 0.0,
+            memory: 0,
+        });
+    }
+
+    isolate.write_box_file("input.txt", stdin)?;
+    let result = isolate.run_with_input("input.txt", &[], limits).await?;
+    let verdict = Verdict::from(result.status);
+
+    Ok(RunOutput {
+        status: verdict.description().to_string(),
+        verdict,
+        compile_output: String::new(),
+        stdout: isolate.read_box_file("out.out", shown_limit).unwrap_or_default(),
+        stderr: isolate.read_box_file("err.out", shown_limit).unwrap_or_default(),
+        time: result.time_usage,
+        memory: result.memory_usage,
+    })
+}
+
+fn challenge_output(successful: bool, status: &str, verdict: Option<Verdict>) -> ChallengeOutput {
+    ChallengeOutput {
+        successful,
+        status: status.to_string(),
+        verdict,
+        message: String::new(),
+        added_test: None,
+    }
+}
+
+async fn challenge(job: ChallengeJob, box_id: u64) -> Result<ChallengeOutput> {
+    let task_config = get_task_config(job.task_id.clone())?;
+    let validator = task_config.validator.as_ref().ok_or(anyhow!("Task has no validator"))?;
+    let solution = task_config.solution.as_ref().ok_or(anyhow!("Task has no model solution"))?;
+
+    let validation = run_program(
+        new_task_isolate(box_id, &job.task_id, validator)?,
+        &job.input,
+        &tool_limits(),
+        SHOWN_OUTPUT_LIMIT
+    ).await?;
+    if validation.verdict != Verdict::Accepted {
+        let mut output = challenge_output(false, "Invalid Input", None);
+        output.message = validation.stderr;
+        return Ok(output);
+    }
+
+    // Judged under the limits the test gets once it is added to the last subtask.
+    let limits = task_config.limits(task_config.subtasks.last(), task_config.num_testcases + 1);
+    let expected = run_program(
+        new_task_isolate(box_id, &job.task_id, solution)?,
+        &job.input,
+        &limits,
+        FULL_OUTPUT_LIMIT
+    ).await?;
+    if expected.verdict != Verdict::Accepted {
+        return Err(anyhow!("Model solution failed: {}", expected.status));
+    }
+
+    let mut isolate = new_isolate(box_id, job.code, &job.language)?;
+    use_task_checker(&mut isolate, &job.task_id, &task_config);
+    let verdict = match isolate.init().await {
+        Ok(()) => judge_challenge(&mut isolate, &job.input, &expected.stdout, &limits).await,
+        Err(err) => Err(err),
+    };
+    isolate.cleanup().await?;
+    let verdict = verdict?;
+
+    if verdict == Verdict::CompilationError {
+        return Ok(challenge_output(false, "Target Compilation Error", Some(verdict)));
+    }
+    if verdict == Verdict::Accepted {
+        return Ok(challenge_output(false, "Hack Unsuccessful", Some(verdict)));
+    }
+
+    let mut output = challenge_output(true, "Hack Successful", Some(verdict));
+    if job.add_to_tests {
+        let test_index = add_test(&job.task_id, &job.input, &expected.stdout)?;
+        output.added_test = Some(test_index);
+    }
+
+    Ok(output)
+}
+
+async fn judge_challenge(
+    isolate: &mut Isolate,
+    input: &str,
+    expected: &str,
+    limits: &Limits
+) -> Result<Verdict> {
+    if isolate.compile().await?.status == RunVerdict::CompilationError {
+        return Ok(Verdict::CompilationError);
+    }
+
+    isolate.write_box_file("hack.in", input)?;
+    isolate.write_box_file("hack.sol", expected)?;
+    let result = isolate.run_with_input("hack.in", &[], limits).await?;
+    if result.status != RunVerdict::VerdictOK {
+        return Ok(Verdict::from(result.status));
+    }
+
+    if isolate.check_files("hack.in", "hack.sol").await? {
+        Ok(Verdict::Accepted)
+    } else {
+        Ok(Verdict::WrongAnswer)
+    }
+}
+
+/// Appends the input as a new last test, in the last subtask when the task has subtasks.
+fn add_test(task_id: &str, input: &str, answer: &str) -> Result<u64> {
+    update_task_config(task_id, |task_config| {
+        let test_index = task_config.num_testcases + 1;
+        let testcases_path = task_dir(task_id).join("testcases");
+        fs::write(testcases_path.join(format!("{}.in", test_index)), input)?;
+        fs::write(testcases_path.join(format!("{}.sol", test_index)), answer)?;
+
+        task_config.num_testcases = test_index;
+        if let Some(subtask) = task_config.subtasks.last_mut() {
+            subtask.num_testcases += 1;
+        }
+
+        Ok(test_index)
+    })
+}
+
+async fn stress(job: StressJob, box_id: u64) -> Result<StressOutput> {
+    let task_config = get_task_config(job.task_id.clone())?;
+
+    let mut generator = new_isolate(box_id, job.generator.code, &job.generator.language)?;
+    let mut reference = new_isolate(box_id + 1, job.reference.code, &job.reference.language)?;
+    let mut candidate = new_isolate(box_id + 2, job.candidate.code, &job.candidate.language)?;
+    use_task_checker(&mut candidate, &job.task_id, &task_config);
+
+    let mut initialized = Ok(());
+    for isolate in [&mut generator, &mut reference, &mut candidate] {
+        if initialized.is_ok() {
+            initialized = isolate.init().await;
+        }
+    }
+
+    let limits = task_config.limits(None, 0);
+    let output = match initialized {
+        Ok(()) => {
+            stress_in_boxes(
+                [&mut generator, &mut reference, &mut candidate],
+                &limits,
+                job.seed,
+                job.iterations.min(MAX_STRESS_ITERATIONS),
+                Instant::now() + STRESS_TIME_BUDGET
+            ).await
+        }
+        Err(err) => Err(err),
+    };
+
+    generator.cleanup().await?;
+    reference.cleanup().await?;
+    candidate.cleanup().await?;
+
+    output
+}
+
+async fn stress_in_boxes(
+    [generator, reference, candidate]: [&mut Isolate; 3],
+    limits: &Limits,
+    first_seed: u64,
+    iterations: u64,
+    deadline: Instant
+) -> Result<StressOutput> {
+    for (name, isolate) in [("Generator", &mut *generator), ("Reference", &mut *reference)] {
+        let compile_result = isolate.compile().await?;
+        if compile_result.status == RunVerdict::CompilationError {
+            return Err(anyhow!("{} failed to compile: {}", name, compile_result.message));
+        }
+    }
+    if candidate.compile().await?.status == RunVerdict::CompilationError {
+        return Ok(StressOutput {
+            found: true,
+            verdict: Some(Verdict::CompilationError),
+            ..Default::default()
+        });
+    }
+
+    for iteration in 1..=iterations {
+        if Instant::now() >= deadline {
+            return Ok(StressOutput {
+                iterations: iteration - 1,
+                timed_out: true,
+                ..Default::default()
+            });
+        }
+        let seed = first_seed.wrapping_add(iteration - 1);
+
+        generator.write_box_file("input.txt", "")?;
+        let args = [seed.to_string()];
+        let result = generator.run_with_input("input.txt", &args, &tool_limits()).await?;
+        if result.status != RunVerdict::VerdictOK {
+            return Err(anyhow!("Generator failed with seed {}: {:?}", seed, result.status));
+        }
+        let input = generator.read_box_file("out.out", FULL_OUTPUT_LIMIT)?;
+
+        reference.write_box_file("input.txt", &input)?;
+        let result = reference.run_with_input("input.txt", &[], limits).await?;
+        if result.status != RunVerdict::VerdictOK {
+            return Err(anyhow!("Reference failed with seed {}: {:?}", seed, result.status));
+        }
+        let reference_output = reference.read_box_file("out.out", FULL_OUTPUT_LIMIT)?;
+
+        candidate.write_box_file("input.txt", &input)?;
+        candidate.write_box_file("answer.txt", &reference_output)?;
+        let result = candidate.run_with_input("input.txt", &[], limits).await?;
+        let verdict = if result.status != RunVerdict::VerdictOK {
+            Verdict::from(result.status)
+        } else if candidate.check_files("input.txt", "answer.txt").await? {
+            Verdict::Accepted
+        } else {
+            Verdict::WrongAnswer
+        };
+
+        if verdict != Verdict::Accepted {
+            return Ok(StressOutput {
+                found: true,
+                iterations: iteration,
+                seed: Some(seed),
+                verdict: Some(verdict),
+                input: cut(input),
+                reference_output: cut(reference_output),
+                candidate_output: candidate
+                    .read_box_file("out.out", SHOWN_OUTPUT_LIMIT)
+                    .unwrap_or_default(),
+                timed_out: false,
+            });
+        }
+    }
+
+    Ok(StressOutput {
+        iterations,
+        ..Default::default()
+    })
+}
+
+fn cut(mut text: String) -> String {
+    if text.len() as u64 > SHOWN_OUTPUT_LIMIT {
+        let mut end = SHOWN_OUTPUT_LIMIT as usize;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("...");
+    }
+    text
+}
+
+async fn calibrate(job: CalibrateJob, box_id: u64) -> Result<CalibrateOutput> {
+    let task_config = get_task_config(job.task_id.clone())?;
+    let solution = task_config.solution.clone().ok_or(anyhow!("Task has no model solution"))?;
+    let mut isolate = new_task_isolate(box_id, &job.task_id, &solution)?;
+    use_task_checker(&mut isolate, &job.task_id, &task_config);
+
+    let runs = job.runs.clamp(1, MAX_CALIBRATION_RUNS);
+    let deadline = Instant::now() + CALIBRATION_TIME_BUDGET;
+    let measured = match init_box(&mut isolate).await {
+        Ok(()) => measure_in_box(&mut isolate, &task_config, runs, deadline).await,
+        Err(err) => Err(err),
+    };
+    isolate.cleanup().await?;
+    let (measurements, timed_out) = measured?;
+
+    let worst_time = measurements
+        .iter()
+        .flat_map(|measurement| measurement.times.iter().copied())
+        .fold(0.0, f64::max);
+    // Rounded up to a tenth of a second, which is what setters usually write by hand.
+    let suggested_time_limit = ((worst_time * job.factor * 10.0).ceil() / 10.0).max(0.1);
+    let failed_tests: Vec<u64> = measurements
+        .iter()
+        .filter(|measurement| measurement.verdict != Verdict::Accepted)
+        .map(|measurement| measurement.test_index)
+        .collect();
+
+    // Overrides win over the task's time limit, so writing it would change nothing for the
+    // tests they cover.
+    let overridden = task_config.subtasks
+        .iter()
+        .map(|subtask| &subtask.limits)
+        .chain(task_config.test_limits.values())
+        .any(|limits| limits.time_limit.is_some());
+    let not_written = if !job.write {
+        None
+    } else if timed_out {
+        Some("the time budget ran out before every test was measured")
+    } else if !failed_tests.is_empty() {
+        Some("the model solution failed some tests")
+    } else if overridden {
+        Some("subtasks or tests override the time limit")
+    } else {
+        None
+    };
+    let written = job.write && not_written.is_none();
+    if written {
+        update_task_config(&job.task_id, |task_config| {
+            task_config.time_limit = suggested_time_limit;
+            Ok(())
+        })?;
+    }
+
+    let output = CalibrateOutput {
+        runs,
+        factor: job.factor,
+        worst_time,
+        suggested_time_limit,
+        failed_tests,
+        timed_out,
+        written,
+        not_written: not_written.map(str::to_string),
+        measurements,
+    };
+    let calibration_path = task_dir(&job.task_id).join("calibration.json");
+    fs::write(calibration_path, serde_json::to_string_pretty(&output)?)?;
+
+    Ok(output)
+}
+
+/// Times are measured under `MAX_TIME_LIMIT` rather than the task's limit, so a limit that is
+/// currently too tight still gives a useful measurement. Returns the measurements so far and
+/// `true` once `deadline` has passed.
+async fn measure_in_box(
+    isolate: &mut Isolate,
+    task_config: &TaskConfig,
+    runs: u64,
+    deadline: Instant
+) -> Result<(Vec<Measurement>, bool)> {
+    let compile_result = isolate.compile().await?;
+    if compile_result.status == RunVerdict::CompilationError {
+        return Err(anyhow!("Model solution failed to compile: {}", compile_result.message));
+    }
+    isolate.copy_testcases().await?;
+
+    let mut measurements = vec![];
+    for test_index in 1..=task_config.num_testcases {
+        let subtask = task_config.subtask_of(test_index).map(|(_, subtask)| subtask);
+        let limits = Limits {
+            time_limit: MAX_TIME_LIMIT,
+            ..task_config.limits(subtask, test_index)
+        };
+
+        let mut measurement = Measurement {
+            test_index,
+            verdict: Verdict::Accepted,
+            times: vec![],
+            memory: 0,
+        };
+        for _ in 0..runs {
+            if Instant::now() >= deadline {
+                if !measurement.times.is_empty() {
+                    measurements.push(measurement);
+                }
+                return Ok((measurements, true));
+            }
+            let result = isolate.run(test_index, &limits).await?;
+            measurement.times.push(result.time_usage);
+            measurement.memory = measurement.memory.max(result.memory_usage);
+
+            if result.status != RunVerdict::VerdictOK {
+                measurement.verdict = Verdict::from(result.status);
+                break;
+            }
+            if !isolate.check(test_index).await? {
+                measurement.verdict = Verdict::WrongAnswer;
+                break;
+            }
+        }
+        measurements.push(measurement);
+    }
+
+    Ok((measurements, false))
+}
@@ -1,24 +1,46 @@
-use std::{ sync::Arc, process::exit, time::Duration };
+use std::{ sync::{ Arc, RwLock }, process::exit, time::Duration };
 use axum::{ extract::DefaultBodyLimit, http::Method, routing::{ delete, get, post }, Router };
 use lapin::Channel;
-use tokio::{ spawn, time::interval };
+use tokio::{ select, spawn, time::interval };
+use tokio::signal::unix::{ signal, SignalKind };
 use log::{ info, warn };
-use tokio::time::sleep;
 use tokio_postgres::Client;
 use dotenv::dotenv;
 use postgres_openssl::MakeTlsConnector;
 use openssl::ssl::{ SslConnector, SslMethod };
 use tower_http::cors::{ Any, CorsLayer };
-use http::header::CONTENT_TYPE;
+use http::header::{ AUTHORIZATION, CONTENT_TYPE };
+use clap::Parser;
 
 pub mod routes;
 pub mod helper;
 pub mod rbmq;
+pub mod broker;
 pub mod isolate;
 pub mod runner;
+pub mod jobs;
+pub mod validate;
+pub mod import;
+pub mod settings;
+pub mod cli;
+
+/// How often config.json is checked for changes.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 pub struct AppState {
-    channel: Channel,
+    /// Replaced by the broker when it reconnects.
+    channel: RwLock<Channel>,
+}
+
+impl AppState {
+    /// The channel to publish on.
+    pub fn channel(&self) -> Channel {
+        self.channel.read().unwrap().clone()
+    }
+
+    pub fn set_channel(&self, channel: Channel) {
+        *self.channel.write().unwrap() = channel;
+    }
 }
 
 #[tokio::main]
@@ -27,9 +49,31 @@ async fn main() {
 
     tracing_subscriber::fmt::init();
 
+    let cli = cli::Cli::parse();
+    let settings = match settings::init_settings(&cli.settings) {
+        Ok(settings) => settings,
+        Err(err) => {
+            warn!("{}", err);
+            exit(2);
+        }
+    };
+    if let Some(command) = cli.command {
+        exit(cli::run_command(command).await);
+    }
+
     info!(" Starting...");
+    settings::print_summary(settings);
 
-    let postgres_url = std::env::var("POSTGRES_URL").expect("POSTGRES_URL not found");
+    if let Err(err) = helper::get_config() {
+        warn!("Failed to load config.json: {}", err);
+        exit(1);
+    }
+    spawn(watch_config());
+
+    let Some(postgres_url) = settings.postgres_url.clone() else {
+        warn!("postgres_url is not set");
+        exit(2);
+    };
 
     let builder = SslConnector::builder(SslMethod::tls()).unwrap();
     let mut connector = MakeTlsConnector::new(builder.build());
@@ -52,7 +96,7 @@ async fn main() {
 
     let db_client = client.clone();
     tokio::spawn(async move {
-        let mut interval = interval(Duration::from_secs(240));
+        let mut interval = interval(Duration::from_secs(settings.heartbeat_interval));
         loop {
             interval.tick().await;
             if let Err(e) = db_client.simple_query("SELECT 1").await {
@@ -62,77 +106,30 @@ async fn main() {
         }
     });
 
-    let rbmq_url = std::env::var("RBMQ_URL").expect("RBMQ_URL not found");
-
-    let max_retries = 5;
-    let mut retries = 0;
+    if settings.rbmq_url.is_none() {
+        warn!("rbmq_url is not set");
+        exit(2);
+    }
 
-    let consumer_channel = loop {
-        match rbmq::get_channel(&rbmq_url).await {
-            Ok(channel) => {
-                break channel;
-            }
-            Err(err) => {
-                warn!("Failed to create RabbitMQ channel: {:?}", err);
-                retries += 1;
-                if retries >= max_retries {
-                    warn!("Reached maximum retry limit. Exiting...");
-                    exit(1);
-                }
-                sleep(Duration::from_secs(5)).await;
-            }
+    let (connection, channel) = match broker::connect_at_startup().await {
+        Ok(connected) => connected,
+        Err(err) => {
+            warn!("Failed to connect to RabbitMQ: {:?}", err);
+            warn!("Reached maximum retry limit. Exiting...");
+            exit(1);
         }
     };
 
     let shared_state = Arc::new(AppState {
-        channel: consumer_channel.clone(),
+        channel: RwLock::new(channel),
     });
 
-    loop {
-        match rbmq::create_queue(consumer_channel.clone(), "queue".to_string()).await {
-            Ok(_) => {
-                break;
-            }
-            Err(err) => {
-                warn!("Failed to create RabbitMQ queue: {:?}", err);
-                sleep(Duration::from_secs(5)).await;
-            }
-        }
-    }
-
-    let max_worker_env = std::env::var("MAX_WORKER").expect("MAX_WORKER not found");
-    let max_worker = match max_worker_env.parse::<i32>() {
-        Ok(num) => num,
-        Err(_) => {
-            warn!("MAX_WORKER must be integer");
-            0
-        }
-    };
-
-    let mut join_handles = Vec::new();
-
-    for i in 0..max_worker {
-        let channel = consumer_channel.clone();
-        let db_client = client.clone();
-        let join_handle = spawn(async move {
-            rbmq::create_consumer(
-                channel,
-                db_client,
-                "queue".to_string(),
-                format!("consumer {}", i)
-            ).await.expect("Unable to create RabbitMQ consumer");
-        });
-        join_handles.push(join_handle);
-    }
-
-    let consumer_handler = spawn(async move {
-        for handle in join_handles {
-            let _ = handle.await;
-        }
-    });
+    let consumer_handler = spawn(
+        broker::supervise(connection, Arc::clone(&shared_state), client.clone())
+    );
 
     let cors = CorsLayer::new()
-        .allow_headers([CONTENT_TYPE])
+        .allow_headers([AUTHORIZATION, CONTENT_TYPE])
         .allow_methods(vec![Method::GET, Method::POST, Method::DELETE])
         .allow_origin(Any);
 
@@ -145,6 +142,29 @@ async fn main() {
                 move |body| routes::submission::create_submission(body, shared_state)
             })
         )
+        .route(
+            "/api/run",
+            post({
+                let shared_state = Arc::clone(&shared_state);
+                move |body| routes::run::run_code(body, shared_state)
+            })
+        )
+        .route(
+            "/api/challenge",
+            post({
+                let shared_state = Arc::clone(&shared_state);
+                move |headers, body| {
+                    routes::challenge::create_challenge(headers, body, shared_state)
+                }
+            })
+        )
+        .route(
+            "/api/stress",
+            post({
+                let shared_state = Arc::clone(&shared_state);
+                move |headers, body| routes::stress::create_stress(headers, body, shared_state)
+            })
+        )
         .route(
             "/api/task/:id",
             get({
@@ -154,9 +174,19 @@ async fn main() {
         )
         .route(
             "/api/task/:id",
-            post(routes::task::upload_task).layer(DefaultBodyLimit::max(1024 * 1000 * 10))
+            post(routes::task::upload_task).layer(DefaultBodyLimit::max(settings.upload_limit))
         )
         .route("/api/task/:id", delete(routes::task::delete_task))
+        .route("/api/task/:id/export", get(routes::task::export_task))
+        .route(
+            "/api/task/:id/calibrate",
+            post({
+                let shared_state = Arc::clone(&shared_state);
+                move |headers, path, body| {
+                    routes::task::calibrate_task(headers, path, body, shared_state)
+                }
+            })
+        )
         .route(
             "/api/desc/:id",
             get({
@@ -171,9 +201,29 @@ async fn main() {
                 move |path| routes::manifest::get_manifest(path, shared_state)
             })
         )
+        .route("/api/manifest/:id/migrate", post(routes::manifest::migrate_manifest))
+        .route("/api/config/reload", post(routes::config::reload_config))
+        .route(
+            "/api/dead-letter",
+            get({
+                let shared_state = Arc::clone(&shared_state);
+                move |headers, query| {
+                    routes::dead_letter::list_dead_letters(headers, query, shared_state)
+                }
+            })
+        )
+        .route(
+            "/api/dead-letter/replay",
+            post({
+                let shared_state = Arc::clone(&shared_state);
+                move |headers, query| {
+                    routes::dead_letter::replay_dead_letters(headers, query, shared_state)
+                }
+            })
+        )
         .layer(cors);
 
-    let port = "0.0.0.0:5000";
+    let port = &settings.bind_address;
     let listener = tokio::net::TcpListener::bind(port).await.unwrap();
 
     let api_handler = spawn(async move { axum::serve(listener, app).await.unwrap() });
@@ -191,3 +241,32 @@ async fn main() {
         }
     }
 }
+
+/// Reloads config.json when its modification time changes and on SIGHUP.
+async fn watch_config() {
+    let config_path = std::env::current_dir().unwrap_or_default().join("config.json");
+    let modified = || std::fs::metadata(&config_path).and_then(|metadata| metadata.modified()).ok();
+
+    let mut hangup = signal(SignalKind::hangup()).expect("Unable to listen for SIGHUP");
+    let mut interval = interval(CONFIG_POLL_INTERVAL);
+    let mut last_modified = modified();
+
+    loop {
+        select! {
+            _ = interval.tick() => {
+                let current = modified();
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+            }
+            _ = hangup.recv() => {
+                info!(" [x] Got SIGHUP");
+            }
+        }
+
+        if let Err(err) = helper::reload_config() {
+            warn!("Failed to reload config.json, keeping the current one: {}", err);
+        }
+    }
+}
@@ -1,41 +1,112 @@
 use lapin::{
-    options::{ BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions },
-    types::FieldTable,
+    options::{
+        BasicAckOptions,
+        BasicConsumeOptions,
+        BasicGetOptions,
+        BasicNackOptions,
+        BasicPublishOptions,
+        BasicQosOptions,
+        ConfirmSelectOptions,
+        QueueDeclareOptions,
+        QueueDeleteOptions,
+    },
+    publisher_confirm::Confirmation,
+    message::Delivery,
+    types::{ AMQPValue, FieldTable, ShortString },
     BasicProperties,
     Channel,
     Connection,
     ConnectionProperties,
 };
+use futures::stream::{ select_with_strategy, BoxStream, PollNext };
 use futures::StreamExt;
 use log::{ info, warn };
 use serde::{ Deserialize, Serialize };
 use tokio_postgres::Client;
-use crate::runner::run;
-use anyhow::Result;
+use tokio::sync::mpsc::{ unbounded_channel, UnboundedReceiver };
+use tokio::time::{ timeout_at, Duration, Instant };
+use crate::helper::FeedbackLevel;
+use crate::isolate::JOB_BOX_START;
+use crate::runner::{ public_result, run, Progress, RunResult, Verdict };
+use crate::jobs::{ self, Job, JobReply };
+use crate::settings::settings;
+use anyhow::{ anyhow, Result };
+use uuid::Uuid;
 use crate::Arc;
 
+/// AMQP delivery mode of messages the broker writes to disk.
+const PERSISTENT: u8 = 2;
+
+/// Minimum time between two progress writes for the same submission.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Lane a submission is judged in. Contest workers judge practice submissions only when no
+/// contest submission is waiting.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    #[default]
+    Contest,
+    /// Practice submissions and rejudges.
+    Practice,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Payload {
     task_id: String,
     submission_id: u64,
     code: String,
     language: String,
+    #[serde(default)]
+    priority: Priority,
+}
+
+/// Queue of the submissions of a lane.
+pub fn submission_queue_name(priority: Priority) -> String {
+    let queue_name = settings().queue_name.clone();
+    match priority {
+        Priority::Contest => queue_name,
+        Priority::Practice => format!("{}.practice", queue_name),
+    }
 }
 
-pub async fn get_channel(rmbq_url: &str) -> Result<Channel> {
+pub async fn get_connection(rmbq_url: &str) -> Result<Connection> {
     let conn = Connection::connect(rmbq_url, ConnectionProperties::default()).await?;
+
+    Ok(conn)
+}
+
+/// Opens a channel of its own for one worker. With a prefetch count RabbitMQ only hands the
+/// worker that many unacknowledged messages, so work is spread over idle workers instead of
+/// piling up on the first one. The count covers the whole channel, not each consumer on it,
+/// so a worker reading two queues does not take a second message while it is busy.
+pub async fn create_channel(conn: &Connection, prefetch: Option<u16>) -> Result<Channel> {
     let channel = conn.create_channel().await?;
+    if let Some(prefetch) = prefetch {
+        channel.basic_qos(prefetch, BasicQosOptions { global: true }).await?;
+    }
 
     Ok(channel)
 }
 
+/// Makes the broker acknowledge every message published on the channel, which
+/// `publish_message` waits for.
+pub async fn enable_confirms(channel: &Channel) -> Result<()> {
+    channel.confirm_select(ConfirmSelectOptions::default()).await?;
+
+    Ok(())
+}
+
+/// Publishes a submission as a persistent message and returns once the broker has taken
+/// responsibility for it. A message that reaches no queue is an error.
 pub async fn publish_message(
     channel: Channel,
     routing_key: String,
     task_id: String,
     submission_id: u64,
     code: String,
-    language: String
+    language: String,
+    priority: Priority
 ) -> Result<()> {
     info!(" [x] Sent to {:?} {:?}", routing_key, submission_id);
 
@@ -44,18 +115,22 @@ pub async fn publish_message(
         submission_id,
         code,
         language,
+        priority,
     };
     let payload = serde_json::to_string(&submission_payload)?;
 
-    channel.basic_publish(
+    let confirmation = channel.basic_publish(
         "",
         &routing_key,
-        BasicPublishOptions::default(),
+        BasicPublishOptions {
+            mandatory: true,
+            ..BasicPublishOptions::default()
+        },
         payload.as_bytes(),
-        BasicProperties::default()
-    ).await?;
+        BasicProperties::default().with_delivery_mode(PERSISTENT)
+    ).await?.await?;
 
-    Ok(())
+    confirmed(confirmation, &routing_key)
 }
 
 pub async fn create_queue(channel: Channel, queue_name: String) -> Result<()> {
@@ -63,86 +138,655 @@ pub async fn create_queue(channel: Channel, queue_name: String) -> Result<()> {
 
     channel.queue_declare(
         &queue_name,
-        QueueDeclareOptions::default(),
+        QueueDeclareOptions {
+            durable: true,
+            ..QueueDeclareOptions::default()
+        },
+        FieldTable::default()
+    ).await?;
+
+    Ok(())
+}
+
+/// Writes "Running on test k/N" and the results so far while a submission is being judged,
+/// at most once per `PROGRESS_INTERVAL`. Progress that arrives in between is written when the
+/// interval is over, unless judging has finished by then.
+async fn report_progress(
+    db_client: Arc<Client>,
+    submission_id: u64,
+    mut receiver: UnboundedReceiver<Progress>
+) {
+    let mut results: Vec<RunResult> = vec![];
+    let mut feedback = FeedbackLevel::default();
+    let mut pending: Option<(u64, u64)> = None;
+    let mut last_update: Option<Instant> = None;
+
+    loop {
+        let next_update = last_update.map(|instant| instant + PROGRESS_INTERVAL);
+        let received = match (pending, next_update) {
+            (Some(_), Some(next_update)) =>
+                match timeout_at(next_update, receiver.recv()).await {
+                    Ok(received) => received,
+                    Err(_) => {
+                        if let Some((tests_done, num_testcases)) = pending.take() {
+                            write_progress(
+                                &db_client,
+                                submission_id,
+                                tests_done,
+                                num_testcases,
+                                &results,
+                                feedback
+                            ).await;
+                        }
+                        last_update = Some(Instant::now());
+                        continue;
+                    }
+                }
+            _ => receiver.recv().await,
+        };
+
+        let Some(progress) = received else {
+            break;
+        };
+        // A sample is sent again by the main pass, and tests stay in order for the subtask
+        // summaries.
+        results.retain(|result| result.test_index != progress.result.test_index);
+        let position = results.partition_point(|result| {
+            result.test_index < progress.result.test_index
+        });
+        results.insert(position, progress.result);
+        feedback = progress.feedback;
+        pending = Some((progress.tests_done, progress.num_testcases));
+
+        if next_update.is_none_or(|next_update| Instant::now() >= next_update) {
+            write_progress(
+                &db_client,
+                submission_id,
+                progress.tests_done,
+                progress.num_testcases,
+                &results,
+                feedback
+            ).await;
+            pending = None;
+            last_update = Some(Instant::now());
+        }
+    }
+}
+
+async fn write_progress(
+    db_client: &Client,
+    submission_id: u64,
+    tests_done: u64,
+    num_testcases: u64,
+    results: &[RunResult],
+    feedback: FeedbackLevel
+) {
+    let running_test = std::cmp::min(tests_done + 1, num_testcases);
+    let status = format!("Running on test {}/{}", running_test, num_testcases);
+    let data = serde_json::to_value(results);
+    let public_data = public_result(results, feedback);
+    let (Ok(data), Ok(public_data)) = (data, public_data) else {
+        return;
+    };
+
+    if
+        let Err(err) = db_client.query_opt(
+            "UPDATE submission SET status = $1, public_status = $1, result = $2, \
+             public_result = $3 WHERE id = $4",
+            &[&status, &data, &public_data, &(submission_id as i32)]
+        ).await
+    {
+        warn!(" [x] {} Failed to update progress: {}", submission_id, err);
+    }
+}
+
+/// Header counting how often a submission has been tried.
+const ATTEMPTS_HEADER: &str = "x-attempts";
+/// Header with the last error of a dead-lettered submission.
+const ERROR_HEADER: &str = "x-error";
+
+/// Retry delays double up to this many times and then stay the same.
+const MAX_RETRY_LEVEL: u64 = 16;
+
+/// Seconds a submission waits before its next attempt, after `attempts` failed ones.
+fn retry_delay(attempts: u64) -> u64 {
+    settings().retry_delay.saturating_mul(1 << retry_level(attempts))
+}
+
+fn retry_level(attempts: u64) -> u64 {
+    attempts.saturating_sub(1).min(MAX_RETRY_LEVEL)
+}
+
+/// Queue failed submissions wait in before RabbitMQ moves them back to `queue_name`. Each
+/// delay has a queue of its own, as RabbitMQ only expires the message at the head of a queue.
+pub fn retry_queue_name(queue_name: &str, attempts: u64) -> String {
+    format!("{}.retry.{}s", queue_name, retry_delay(attempts))
+}
+
+/// Queue of submissions that failed `max_attempts` times or could not be read.
+pub fn dead_letter_queue_name(queue_name: &str) -> String {
+    format!("{}.dead", queue_name)
+}
+
+/// Declares the retry queues, one per delay, and the dead-letter queue of a submission queue.
+/// Messages expire from a retry queue back into `queue_name`.
+pub async fn create_retry_queues(channel: Channel, queue_name: String) -> Result<()> {
+    let durable = QueueDeclareOptions {
+        durable: true,
+        ..QueueDeclareOptions::default()
+    };
+
+    let retries = settings().max_attempts.saturating_sub(1).min(MAX_RETRY_LEVEL + 1);
+    for attempts in 1..=retries {
+        let mut arguments = FieldTable::default();
+        arguments.insert(
+            ShortString::from("x-dead-letter-exchange"),
+            AMQPValue::LongString("".into())
+        );
+        arguments.insert(
+            ShortString::from("x-dead-letter-routing-key"),
+            AMQPValue::LongString(queue_name.as_str().into())
+        );
+        // RabbitMQ takes at most 2^32 - 1 milliseconds.
+        let ttl = retry_delay(attempts).saturating_mul(1000).min(u32::MAX as u64);
+        arguments.insert(ShortString::from("x-message-ttl"), AMQPValue::LongLongInt(ttl as i64));
+
+        let retry_queue_name = retry_queue_name(&queue_name, attempts);
+        channel.queue_declare(&retry_queue_name, durable, arguments).await?;
+    }
+    channel.queue_declare(
+        &dead_letter_queue_name(&queue_name),
+        durable,
         FieldTable::default()
     ).await?;
 
     Ok(())
 }
 
+fn confirmed(confirmation: Confirmation, routing_key: &str) -> Result<()> {
+    match confirmation {
+        Confirmation::Ack(None) | Confirmation::NotRequested => Ok(()),
+        Confirmation::Ack(Some(_)) => Err(anyhow!("No queue {:?} to route to", routing_key)),
+        Confirmation::Nack(_) => Err(anyhow!("The broker refused the message")),
+    }
+}
+
+fn attempts_of(delivery: &Delivery) -> u64 {
+    delivery.properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(ATTEMPTS_HEADER))
+        .and_then(|value| {
+            value
+                .as_long_long_int()
+                .or(value.as_long_int().map(i64::from))
+                .or(value.as_long_uint().map(i64::from))
+        })
+        .map_or(0, |attempts| attempts.max(0) as u64)
+}
+
+/// Publishes a copy of a delivery with its attempts counted and `header` added.
+async fn forward(
+    channel: &Channel,
+    routing_key: &str,
+    delivery: &Delivery,
+    attempts: u64,
+    header: Option<(&str, String)>
+) -> Result<()> {
+    let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+    headers.insert(ATTEMPTS_HEADER.into(), AMQPValue::LongLongInt(attempts as i64));
+    if let Some((name, value)) = header {
+        headers.insert(name.into(), AMQPValue::LongString(value.as_str().into()));
+    }
+
+    let properties = delivery.properties
+        .clone()
+        .with_headers(headers)
+        .with_delivery_mode(PERSISTENT);
+
+    let confirmation = channel.basic_publish(
+        "",
+        routing_key,
+        BasicPublishOptions {
+            mandatory: true,
+            ..BasicPublishOptions::default()
+        },
+        &delivery.data,
+        properties
+    ).await?.await?;
+
+    confirmed(confirmation, routing_key)
+}
+
+/// Judges one submission and stores the result. An error leaves the submission to be retried.
+async fn judge_submission(db_client: &Arc<Client>, payload: Payload) -> Result<()> {
+    let submission_id = payload.submission_id;
+
+    info!(" [*] Judging {}", submission_id);
+
+    let row = db_client.query_opt(
+        "SELECT id FROM submission WHERE id = $1",
+        &[&(submission_id as i32)]
+    ).await?;
+
+    if row.is_none() {
+        warn!(" [x] Submission ID {} not found", submission_id);
+        return Ok(());
+    }
+
+    db_client.query_opt(
+        "UPDATE submission SET status = $1, public_status = $1 WHERE id = $2",
+        &[&"Judging", &(submission_id as i32)]
+    ).await?;
+
+    let (progress_sender, progress_receiver) = unbounded_channel();
+    let progress_handle = tokio::spawn(
+        report_progress(db_client.clone(), submission_id, progress_receiver)
+    );
+
+    let result = run(
+        payload.task_id,
+        submission_id % JOB_BOX_START,
+        payload.code,
+        payload.language,
+        Some(progress_sender)
+    ).await;
+    // Wait for the last progress write so it cannot overwrite the final result.
+    let _ = progress_handle.await;
+
+    let judge_result = result?;
+    info!(" [x] {} Finished", submission_id);
+    let data = serde_json::to_value(&judge_result.result)?;
+    let public_data = judge_result.public_result()?;
+    db_client.query_opt(
+        "UPDATE submission SET status = $1, public_status = $2, verdict = $3, score = $4, time = $5, memory = $6, result = $7, public_result = $8 WHERE id = $9",
+        &[
+            &judge_result.status,
+            &judge_result.public_status(),
+            &judge_result.verdict.code(),
+            &(judge_result.score as i32),
+            &(judge_result.time as i32),
+            &(judge_result.memory as i32),
+            &data,
+            &public_data,
+            &(submission_id as i32),
+        ]
+    ).await?;
+
+    Ok(())
+}
+
+/// Shows that the submission waits for another attempt instead of still being judged.
+async fn mark_retrying(db_client: &Client, submission_id: u64) {
+    let updated = db_client.query_opt(
+        "UPDATE submission SET status = $1, public_status = $1 WHERE id = $2",
+        &[&"Retrying", &(submission_id as i32)]
+    ).await;
+    if let Err(err) = updated {
+        warn!(" [x] {} Failed to set Retrying: {}", submission_id, err);
+    }
+}
+
+async fn mark_judge_error(db_client: &Client, submission_id: u64) {
+    let updated = db_client.query_opt(
+        "UPDATE submission SET status = $1, public_status = $1, verdict = $2 WHERE id = $3",
+        &[
+            &Verdict::JudgeError.description(),
+            &Verdict::JudgeError.code(),
+            &(submission_id as i32),
+        ]
+    ).await;
+    if let Err(err) = updated {
+        warn!(" [x] {} Failed to set Judge Error: {}", submission_id, err);
+    }
+}
+
+/// Database and IO errors, which includes failing to run isolate, may go away on their own.
+/// Anything else, like a manifest that cannot be parsed, fails the same way every time.
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<tokio_postgres::Error>() || cause.is::<std::io::Error>())
+}
+
+/// Judges submissions from `queue_names`, taking a message from a later queue only while the
+/// earlier ones have none. A submission that fails with a transient error is retried after
+/// `retry_delay` seconds, doubled on every attempt. After `max_attempts`, or at once for any
+/// other error, it is marked Judge Error and moved to the dead-letter queue of its queue, as
+/// are messages that cannot be read. Returns when the channel fails.
 pub async fn create_consumer(
     channel: Channel,
     db_client: Arc<Client>,
-    queue_name: String,
+    queue_names: Vec<String>,
     consumer_tag: String
 ) -> Result<()> {
     info!(" [x] Created consumer {:?}", consumer_tag);
 
-    let mut consumer = channel.basic_consume(
-        &queue_name,
-        &consumer_tag,
-        BasicConsumeOptions::default(),
+    let mut deliveries: Option<BoxStream<'static, (String, lapin::Result<Delivery>)>> = None;
+    for queue_name in queue_names {
+        let consumer = channel.basic_consume(
+            &queue_name,
+            &format!("{} {}", consumer_tag, queue_name),
+            BasicConsumeOptions::default(),
+            FieldTable::default()
+        ).await?;
+        let consumer = consumer.map(move |delivery| (queue_name.clone(), delivery)).boxed();
+        deliveries = Some(match deliveries {
+            Some(earlier) => {
+                select_with_strategy(earlier, consumer, |_: &mut ()| PollNext::Left).boxed()
+            }
+            None => consumer,
+        });
+    }
+    let Some(mut deliveries) = deliveries else {
+        return Ok(());
+    };
+
+    info!(" [*] Waiting for messages {:?}", consumer_tag);
+    while let Some((queue_name, delivery)) = deliveries.next().await {
+        let delivery = delivery?;
+        let attempts = attempts_of(&delivery) + 1;
+
+        match serde_json::from_slice::<Payload>(&delivery.data) {
+            Ok(payload) => {
+                let submission_id = payload.submission_id;
+                if let Err(err) = judge_submission(&db_client, payload).await {
+                    warn!(" [x] {} Attempt {} failed: {}", submission_id, attempts, err);
+                    if attempts < settings().max_attempts && is_transient(&err) {
+                        mark_retrying(&db_client, submission_id).await;
+                        forward(
+                            &channel,
+                            &retry_queue_name(&queue_name, attempts),
+                            &delivery,
+                            attempts,
+                            None
+                        ).await?;
+                    } else {
+                        mark_judge_error(&db_client, submission_id).await;
+                        forward(
+                            &channel,
+                            &dead_letter_queue_name(&queue_name),
+                            &delivery,
+                            attempts,
+                            Some((ERROR_HEADER, err.to_string()))
+                        ).await?;
+                    }
+                }
+            }
+            Err(err) => {
+                warn!(" [x] Invalid submission message: {}", err);
+                forward(
+                    &channel,
+                    &dead_letter_queue_name(&queue_name),
+                    &delivery,
+                    attempts,
+                    Some((ERROR_HEADER, format!("Invalid message: {}", err)))
+                ).await?;
+            }
+        }
+        delivery.ack(BasicAckOptions::default()).await?;
+    }
+
+    Ok(())
+}
+
+/// A message in a dead-letter queue.
+#[derive(Debug, Serialize)]
+pub struct DeadLetter {
+    pub submission_id: Option<u64>,
+    pub task_id: Option<String>,
+    pub language: Option<String>,
+    pub attempts: u64,
+    pub error: Option<String>,
+}
+
+/// Lists up to `limit` messages of the dead-letter queue of `queue_name` and leaves them in
+/// it.
+pub async fn peek_dead_letters(
+    channel: Channel,
+    queue_name: String,
+    limit: usize
+) -> Result<Vec<DeadLetter>> {
+    let dead_letter_queue = dead_letter_queue_name(&queue_name);
+    let mut deliveries = vec![];
+    while deliveries.len() < limit {
+        match channel.basic_get(&dead_letter_queue, BasicGetOptions::default()).await? {
+            Some(message) => deliveries.push(message.delivery),
+            None => {
+                break;
+            }
+        }
+    }
+
+    let mut dead_letters = vec![];
+    for delivery in &deliveries {
+        let payload = serde_json::from_slice::<Payload>(&delivery.data).ok();
+        let error = delivery.properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(ERROR_HEADER))
+            .and_then(|value| value.as_long_string())
+            .map(|error| error.to_string());
+        dead_letters.push(DeadLetter {
+            submission_id: payload.as_ref().map(|payload| payload.submission_id),
+            task_id: payload.as_ref().map(|payload| payload.task_id.clone()),
+            language: payload.as_ref().map(|payload| payload.language.clone()),
+            attempts: attempts_of(delivery),
+            error,
+        });
+    }
+    for delivery in deliveries {
+        delivery.nack(BasicNackOptions { requeue: true, ..BasicNackOptions::default() }).await?;
+    }
+
+    Ok(dead_letters)
+}
+
+/// Moves the messages of the dead-letter queue of `queue_name`, or only those of one
+/// submission, back to `queue_name` with their attempts reset. Returns how many were moved.
+pub async fn replay_dead_letters(
+    channel: Channel,
+    queue_name: String,
+    submission_id: Option<u64>
+) -> Result<u64> {
+    let mut kept = vec![];
+    let replayed = move_dead_letters(&channel, &queue_name, submission_id, &mut kept).await;
+
+    // What was not moved goes back to the dead-letter queue, also when moving failed half way.
+    let mut requeued = Ok(());
+    for delivery in kept {
+        let nacked = delivery.nack(BasicNackOptions {
+            requeue: true,
+            ..BasicNackOptions::default()
+        }).await;
+        if let Err(err) = nacked {
+            requeued = Err(err);
+        }
+    }
+
+    let replayed = replayed?;
+    requeued?;
+    Ok(replayed)
+}
+
+/// Republishes the selected dead letters and collects the others in `kept`, together with the
+/// one that failed to move, if any.
+async fn move_dead_letters(
+    channel: &Channel,
+    queue_name: &str,
+    submission_id: Option<u64>,
+    kept: &mut Vec<Delivery>
+) -> Result<u64> {
+    let dead_letter_queue = dead_letter_queue_name(queue_name);
+    let mut replayed = 0;
+
+    loop {
+        let got = channel.basic_get(&dead_letter_queue, BasicGetOptions::default()).await?;
+        let Some(message) = got else {
+            break;
+        };
+        let delivery = message.delivery;
+        let payload = serde_json::from_slice::<Payload>(&delivery.data).ok();
+        let selected = match (&payload, submission_id) {
+            (Some(payload), Some(submission_id)) => payload.submission_id == submission_id,
+            (payload, None) => payload.is_some(),
+            (None, Some(_)) => false,
+        };
+        if !selected {
+            kept.push(delivery);
+            continue;
+        }
+
+        if let Err(err) = republish(channel, queue_name, &delivery.data).await {
+            kept.push(delivery);
+            return Err(err);
+        }
+        delivery.ack(BasicAckOptions::default()).await?;
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
+
+async fn republish(channel: &Channel, queue_name: &str, data: &[u8]) -> Result<()> {
+    let confirmation = channel.basic_publish(
+        "",
+        queue_name,
+        BasicPublishOptions {
+            mandatory: true,
+            ..BasicPublishOptions::default()
+        },
+        data,
+        BasicProperties::default().with_delivery_mode(PERSISTENT)
+    ).await?.await?;
+
+    confirmed(confirmation, queue_name)
+}
+
+/// Publishes a job and waits for its reply on a private, server-named queue.
+pub async fn call_job(
+    channel: Channel,
+    routing_key: String,
+    job: &Job,
+    timeout: Duration
+) -> Result<JobReply> {
+    let reply_queue = channel.queue_declare(
+        "",
+        QueueDeclareOptions {
+            exclusive: true,
+            auto_delete: true,
+            ..QueueDeclareOptions::default()
+        },
         FieldTable::default()
     ).await?;
+    let reply_queue_name = reply_queue.name().as_str().to_string();
 
-    info!(" [*] Waiting for messages {:?}", consumer_tag);
-    while let Some(delivery) = consumer.next().await {
-        if let Ok(delivery) = delivery {
-            let data = std::str::from_utf8(&delivery.data)?;
-            let payload: Payload = serde_json::from_str(data)?;
+    let mut consumer = channel.basic_consume(
+        &reply_queue_name,
+        "",
+        BasicConsumeOptions {
+            no_ack: true,
+            ..BasicConsumeOptions::default()
+        },
+        FieldTable::default()
+    ).await?;
 
-            let task_id = payload.task_id;
-            let submission_id = payload.submission_id;
-            let language = payload.language;
-            let code = payload.code;
+    let correlation_id = Uuid::new_v4().to_string();
+    let payload = serde_json::to_string(job)?;
 
-            info!(" [*] Judging {}", submission_id);
+    info!(" [x] Sent job to {:?} {:?}", routing_key, correlation_id);
 
-            let row = db_client.query_opt(
-                "SELECT id FROM submission WHERE id = $1",
-                &[&(submission_id as i32)]
-            ).await?;
+    channel.basic_publish(
+        "",
+        &routing_key,
+        BasicPublishOptions::default(),
+        payload.as_bytes(),
+        BasicProperties::default()
+            .with_reply_to(ShortString::from(reply_queue_name.clone()))
+            .with_correlation_id(ShortString::from(correlation_id.clone()))
+            // Nobody waits for the reply any more once the caller has timed out, so a job
+            // still queued by then is dropped instead of run.
+            .with_expiration(ShortString::from(timeout.as_millis().to_string()))
+    ).await?;
 
-            if row.is_none() {
-                warn!(" [x] Submission ID {} not found", submission_id);
-                delivery.ack(BasicAckOptions::default()).await?;
-                continue;
+    let reply = tokio::time::timeout(timeout, async {
+        while let Some(delivery) = consumer.next().await {
+            let delivery = delivery?;
+            let is_reply = delivery.properties
+                .correlation_id()
+                .as_ref()
+                .is_some_and(|id| id.as_str() == correlation_id);
+            if is_reply {
+                return Ok(serde_json::from_slice::<JobReply>(&delivery.data)?);
             }
+        }
+        Err(anyhow!("Reply queue closed"))
+    }).await;
 
-            db_client.query_opt(
-                "UPDATE submission SET status = $1 WHERE id = $2",
-                &[&"Judging", &(submission_id as i32)]
-            ).await?;
+    channel.queue_delete(&reply_queue_name, QueueDeleteOptions::default()).await?;
 
-            let result = run(task_id, submission_id, code, language).await;
-
-            match result {
-                Ok(judge_result) => {
-                    info!(" [x] {} Finished", submission_id);
-                    let data = serde_json::to_value(&judge_result.result).unwrap();
-                    db_client.query_opt(
-                        "UPDATE submission SET status = $1, score = $2, time = $3, memory = $4, result = $5 WHERE id = $6",
-                        &[
-                            &judge_result.status,
-                            &(judge_result.score as i32),
-                            &(judge_result.time as i32),
-                            &(judge_result.memory as i32),
-                            &data,
-                            &(submission_id as i32),
-                        ]
-                    ).await?;
-                }
-                Err(_err) => {
-                    warn!(" [x] {} {}", submission_id, _err);
-                    db_client.query_opt(
-                        "UPDATE submission SET status = 'Judge Error' WHERE id = $1",
-                        &[&(submission_id as i32)]
-                    ).await?;
-                }
+    match reply {
+        Ok(reply) => reply,
+        Err(_) => Err(anyhow!("Timed out waiting for the job result")),
+    }
+}
+
+pub async fn create_job_consumer(
+    channel: Channel,
+    queue_name: String,
+    consumer_tag: String,
+    box_id: u64
+) -> Result<()> {
+    info!(" [x] Created job consumer {:?}", consumer_tag);
+
+    let mut consumer = channel.basic_consume(
+        &queue_name,
+        &consumer_tag,
+        BasicConsumeOptions::default(),
+        FieldTable::default()
+    ).await?;
+
+    info!(" [*] Waiting for jobs {:?}", consumer_tag);
+    while let Some(delivery) = consumer.next().await {
+        let delivery = delivery?;
+        let reply: JobReply = match serde_json::from_slice::<Job>(&delivery.data) {
+            Ok(job) => jobs::execute(job, box_id).await.map_err(|err| err.to_string()),
+            Err(err) => Err(format!("Invalid job: {}", err)),
+        };
+        if let Err(err) = &reply {
+            warn!(" [x] Job failed: {}", err);
+        }
+
+        if let Some(reply_to) = delivery.properties.reply_to() {
+            let mut properties = BasicProperties::default();
+            if let Some(correlation_id) = delivery.properties.correlation_id() {
+                properties = properties.with_correlation_id(correlation_id.clone());
             }
-            delivery.ack(BasicAckOptions::default()).await?;
+            channel.basic_publish(
+                "",
+                reply_to.as_str(),
+                BasicPublishOptions::default(),
+                serde_json::to_string(&reply)?.as_bytes(),
+                properties
+            ).await?;
         }
+        delivery.ack(BasicAckOptions::default()).await?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn retries_only_transient_errors() {
+        let io_error = std::io::Error::other("isolate is busy");
+        let io_error = Err::<(), _>(io_error).context("Failed to run the test").unwrap_err();
+
+        assert!(is_transient(&io_error));
+        assert!(!is_transient(&anyhow!("Unsupported language: brainfuck")));
+        assert!(!is_transient(&serde_json::from_str::<Payload>("{").unwrap_err().into()));
+    }
+}
@@ -0,0 +1,17 @@
+use axum::{ http::{ header, HeaderMap, StatusCode }, Json };
+use serde_json::{ json, Value };
+use crate::settings::settings;
+
+/// Refuses requests without `Authorization: Bearer <admin_token>`. Everything is refused while
+/// no `admin_token` is set.
+pub fn require_admin(headers: &HeaderMap) -> Result<(), (StatusCode, Json<Value>)> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match (&settings().admin_token, token) {
+        (Some(admin_token), Some(token)) if admin_token == token => Ok(()),
+        _ => Err((StatusCode::FORBIDDEN, Json(json!({ "error": "Admin token required" })))),
+    }
+}
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+use std::time::Duration;
+use axum::{ response::IntoResponse, http::{ HeaderMap, StatusCode }, Json };
+use serde_json::json;
+use crate::helper::{ get_language_config, get_task_config };
+use crate::jobs::{ ChallengeJob, Job };
+use crate::routes::auth::require_admin;
+use crate::settings::settings;
+use crate::{ rbmq, AppState };
+
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Anyone may challenge, but only admins may add the input to the task's tests.
+pub async fn create_challenge(
+    headers: HeaderMap,
+    Json(req): Json<ChallengeJob>,
+    state: Arc<AppState>
+) -> impl IntoResponse {
+    if req.add_to_tests {
+        if let Err(refused) = require_admin(&headers) {
+            return refused;
+        }
+    }
+    if let Err(err) = get_language_config(&req.language) {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": err.to_string() })));
+    }
+    if get_task_config(req.task_id.clone()).is_err() {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": "Invalid task id" })));
+    }
+
+    let reply = rbmq::call_job(
+        state.channel(),
+        settings().job_queue_name.clone(),
+        &Job::Challenge(req),
+        CHALLENGE_TIMEOUT
+    ).await;
+
+    match reply {
+        Ok(Ok(output)) => (StatusCode::OK, Json(output)),
+        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": err }))),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": err.to_string() }))),
+    }
+}
@@ -0,0 +1,17 @@
+use axum::{ response::IntoResponse, http::{ HeaderMap, StatusCode }, Json };
+use serde_json::json;
+
+use crate::helper;
+use crate::routes::auth::require_admin;
+
+/// Reloads config.json and returns the languages that changed. An invalid file is refused
+/// and the current config stays. Admins only.
+pub async fn reload_config(headers: HeaderMap) -> impl IntoResponse {
+    if let Err(refused) = require_admin(&headers) {
+        return refused;
+    }
+    match helper::reload_config() {
+        Ok(changes) => (StatusCode::OK, Json(json!({ "message": "ok", "changes": changes }))),
+        Err(err) => (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "error": err.to_string() }))),
+    }
+}
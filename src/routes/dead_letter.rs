@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use axum::{ extract::Query, response::IntoResponse, http::{ HeaderMap, StatusCode }, Json };
+use serde::Deserialize;
+use serde_json::json;
+use crate::rbmq::{ self, Priority };
+use crate::routes::auth::require_admin;
+use crate::AppState;
+
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    limit: Option<usize>,
+    #[serde(default)]
+    priority: Priority,
+}
+
+/// Lists the submissions in the dead-letter queue without taking them out. Admins only.
+pub async fn list_dead_letters(
+    headers: HeaderMap,
+    Query(query): Query<ListQuery>,
+    state: Arc<AppState>
+) -> impl IntoResponse {
+    if let Err(refused) = require_admin(&headers) {
+        return refused;
+    }
+    let dead_letters = rbmq::peek_dead_letters(
+        state.channel(),
+        rbmq::submission_queue_name(query.priority),
+        query.limit.unwrap_or(DEFAULT_LIMIT)
+    ).await;
+
+    match dead_letters {
+        Ok(dead_letters) => (StatusCode::OK, Json(json!({ "dead_letters": dead_letters }))),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": err.to_string() }))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReplayQuery {
+    submission_id: Option<u64>,
+    #[serde(default)]
+    priority: Priority,
+}
+
+/// Queues the dead-lettered submissions again, or only the one asked for. Admins only.
+pub async fn replay_dead_letters(
+    headers: HeaderMap,
+    Query(query): Query<ReplayQuery>,
+    state: Arc<AppState>
+) -> impl IntoResponse {
+    if let Err(refused) = require_admin(&headers) {
+        return refused;
+    }
+    let replayed = rbmq::replay_dead_letters(
+        state.channel(),
+        rbmq::submission_queue_name(query.priority),
+        query.submission_id
+    ).await;
+
+    match replayed {
+        Ok(replayed) => (StatusCode::OK, Json(json!({ "message": "ok", "replayed": replayed }))),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": err.to_string() }))),
+    }
+}
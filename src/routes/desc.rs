@@ -10,8 +10,8 @@ use serde::{ Deserialize, Serialize };
 use serde_json::json;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
-use std::env;
 use std::sync::Arc;
+use crate::helper::task_dir;
 use crate::AppState;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -20,13 +20,12 @@ pub struct GetTask {
 }
 
 pub async fn get_desc(Path(task_id): Path<String>, _state: Arc<AppState>) -> impl IntoResponse {
-    let current_dir = env::current_dir().unwrap();
-    let path = current_dir.join("tasks").join(task_id).join("desc.pdf");
+    let path = task_dir(&task_id).join("desc.pdf");
 
     match File::open(&path).await {
         Ok(mut file) => {
             let mut contents = Vec::new();
-            if let Err(_) = file.read_to_end(&mut contents).await {
+            if file.read_to_end(&mut contents).await.is_err() {
                 return (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(json!({ "error": "Failed to read file" })),
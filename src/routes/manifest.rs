@@ -2,7 +2,8 @@ use std::sync::Arc;
 use axum::{ response::IntoResponse, http::StatusCode, Json, extract::Path };
 use serde::{ Deserialize, Serialize };
 use serde_json::json;
-use crate::helper::get_task_config;
+use crate::helper::{ get_task_config, migrate_task_config, MANIFEST_VERSION };
+use crate::validate::validate_task_id;
 use crate::AppState;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -11,12 +12,36 @@ pub struct GetTask {
 }
 
 pub async fn get_manifest(Path(task_id): Path<String>, _state: Arc<AppState>) -> impl IntoResponse {
-    let task_config = get_task_config(task_id);
+    let task_config = get_task_config(task_id.clone());
     match task_config {
         Ok(_) => {}
         Err(_err) => {
             return (StatusCode::NOT_FOUND, Json(json!({ "error": "Invalid task id" })));
         }
     }
-    return (StatusCode::OK, Json(json!(task_config.unwrap())));
+
+    let problems = validate_task_id(&task_id);
+    if !problems.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(
+                json!({
+                    "error": "Task misconfigured",
+                    "problems": problems,
+                    "manifest": task_config.unwrap(),
+                })
+            ),
+        );
+    }
+    (StatusCode::OK, Json(json!(task_config.unwrap())))
+}
+
+pub async fn migrate_manifest(Path(task_id): Path<String>) -> impl IntoResponse {
+    match migrate_task_config(&task_id) {
+        Ok(from) => {
+            let body = json!({ "message": "ok", "from": from, "to": MANIFEST_VERSION });
+            (StatusCode::OK, Json(body))
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, Json(json!({ "error": err.to_string() }))),
+    }
 }
@@ -3,3 +3,9 @@ pub mod task;
 pub mod desc;
 pub mod manifest;
 pub mod healthchecker;
+pub mod run;
+pub mod challenge;
+pub mod stress;
+pub mod config;
+pub mod auth;
+pub mod dead_letter;
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use std::time::Duration;
+use axum::{ response::IntoResponse, http::StatusCode, Json };
+use serde_json::json;
+use crate::helper::get_language_config;
+use crate::jobs::{ Job, RunJob };
+use crate::settings::settings;
+use crate::{ rbmq, AppState };
+
+const RUN_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub async fn run_code(Json(req): Json<RunJob>, state: Arc<AppState>) -> impl IntoResponse {
+    if let Err(err) = get_language_config(&req.language) {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": err.to_string() })));
+    }
+    if req.time_limit.is_some_and(|limit| limit <= 0.0) || req.memory_limit == Some(0) {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": "Limits must be positive" })));
+    }
+
+    let reply = rbmq::call_job(
+        state.channel(),
+        settings().job_queue_name.clone(),
+        &Job::Run(req),
+        RUN_TIMEOUT
+    ).await;
+
+    match reply {
+        Ok(Ok(output)) => (StatusCode::OK, Json(output)),
+        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": err }))),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": err.to_string() }))),
+    }
+}
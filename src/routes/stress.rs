@@ -0,0 +1,44 @@
+use std::sync::Arc;
+use std::time::Duration;
+use axum::{ response::IntoResponse, http::{ HeaderMap, StatusCode }, Json };
+use serde_json::json;
+use crate::helper::{ get_language_config, get_task_config };
+use crate::jobs::{ Job, StressJob, STRESS_TIME_BUDGET };
+use crate::routes::auth::require_admin;
+use crate::settings::settings;
+use crate::{ rbmq, AppState };
+
+/// Room for the last iteration started within the job's time budget.
+const STRESS_TIMEOUT: Duration = STRESS_TIME_BUDGET.saturating_add(Duration::from_secs(120));
+
+/// Stress tests hold a job worker for minutes, so they are for admins only.
+pub async fn create_stress(
+    headers: HeaderMap,
+    Json(req): Json<StressJob>,
+    state: Arc<AppState>
+) -> impl IntoResponse {
+    if let Err(refused) = require_admin(&headers) {
+        return refused;
+    }
+    for source in [&req.generator, &req.reference, &req.candidate] {
+        if let Err(err) = get_language_config(&source.language) {
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": err.to_string() })));
+        }
+    }
+    if get_task_config(req.task_id.clone()).is_err() {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": "Invalid task id" })));
+    }
+
+    let reply = rbmq::call_job(
+        state.channel(),
+        settings().job_queue_name.clone(),
+        &Job::Stress(req),
+        STRESS_TIMEOUT
+    ).await;
+
+    match reply {
+        Ok(Ok(output)) => (StatusCode::OK, Json(output)),
+        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": err }))),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": err.to_string() }))),
+    }
+}
@@ -3,7 +3,8 @@ use axum::{ response::IntoResponse, http::StatusCode, Json };
 use serde::{ Deserialize, Serialize };
 use serde_json::json;
 use crate::helper::get_language_config;
-use crate::{ rbmq, AppState };
+use crate::rbmq::{ self, Priority };
+use crate::AppState;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateSubmission {
@@ -11,6 +12,8 @@ pub struct CreateSubmission {
     submission_id: u64,
     code: String,
     language: String,
+    #[serde(default)]
+    priority: Priority,
 }
 
 pub async fn create_submission(
@@ -24,14 +27,18 @@ pub async fn create_submission(
         }
     }
 
-    rbmq::publish_message(
-        state.channel.to_owned(),
-        "queue".to_string(),
+    let published = rbmq::publish_message(
+        state.channel(),
+        rbmq::submission_queue_name(req.priority),
         req.task_id,
         req.submission_id,
         req.code,
-        req.language
-    ).await.expect("Unable to publish RabbitMQ message");
+        req.language,
+        req.priority
+    ).await;
 
-    return (StatusCode::CREATED, Json(json!({ "message": "success" })));
+    match published {
+        Ok(()) => (StatusCode::CREATED, Json(json!({ "message": "success" }))),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": err.to_string() }))),
+    }
 }
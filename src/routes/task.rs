@@ -1,4 +1,3 @@
-use std::path::PathBuf;
 use std::sync::Arc;
 use axum::{
     response::IntoResponse,
@@ -6,28 +5,37 @@ use axum::{
     http::StatusCode,
     Json,
     http::header,
+    http::HeaderMap,
     extract::Path,
     extract::Multipart,
+    extract::Query,
 };
+use std::time::Duration;
 use futures::StreamExt;
+use serde::Deserialize;
 use serde_json::json;
-use crate::AppState;
+use crate::helper::{ get_task_config, task_dir };
+use crate::import::{ export_package, import_package };
+use crate::validate::validate_task_id;
+use crate::jobs::{ CalibrateJob, Job, CALIBRATION_TIME_BUDGET };
+use crate::routes::auth::require_admin;
+use crate::settings::settings;
+use crate::{ rbmq, AppState };
 use std::fs::{ self, File };
 use std::io::{ Cursor, Write };
-use std::env;
 use tokio::io::AsyncReadExt;
+use log::warn;
 
 pub async fn get_task_testcases(
     Path(task_id): Path<String>,
     _state: Arc<AppState>
 ) -> impl IntoResponse {
-    let current_dir = env::current_dir().unwrap();
-    let path = current_dir.join("tasks").join(task_id).join("testcases.zip");
+    let path = task_dir(&task_id).join("testcases.zip");
 
     match tokio::fs::File::open(&path).await {
         Ok(mut file) => {
             let mut contents = Vec::new();
-            if let Err(_) = file.read_to_end(&mut contents).await {
+            if file.read_to_end(&mut contents).await.is_err() {
                 return (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(json!({ "error": "Failed to read file" })),
@@ -47,29 +55,56 @@ pub async fn get_task_testcases(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    /// Package format of the uploaded zip, such as "polygon". Without it the files are the
+    /// task's own files.
+    format: Option<String>,
+}
+
 pub async fn upload_task(
     Path(task_id): Path<String>,
+    Query(query): Query<UploadQuery>,
     mut multipart: Multipart
 ) -> impl IntoResponse {
-    let dir_path = format!("tasks/{}", task_id);
+    let dir_path = task_dir(&task_id);
     fs::create_dir_all(&dir_path).unwrap_or_else(|e| {
         eprintln!("Error creating directory: {}", e);
     });
 
+    let mut warnings = vec![];
     while let Some(mut field) = multipart.next_field().await.unwrap() {
         let file_name = field.file_name().unwrap().to_string();
-        let file_path = format!("{}/{}", dir_path, file_name);
-        let mut file = File::create(&file_path).expect("Error creating file");
 
         let mut data = vec![];
         while let Some(chunk) = field.next().await {
-            let chunk_data = chunk.unwrap();
-            file.write_all(&chunk_data).expect("Error writing to file");
-            data.extend_from_slice(&chunk_data);
+            data.extend_from_slice(&chunk.unwrap());
         }
 
+        if let Some(format) = &query.format {
+            // Importing copies files and compiles the checker, which blocks.
+            let (format, dir_path) = (format.clone(), dir_path.clone());
+            let imported = tokio::task::spawn_blocking(move || {
+                import_package(&format, &data, &dir_path)
+            }).await;
+            match imported.map_err(anyhow::Error::from).and_then(|imported| imported) {
+                Ok(import_warnings) => warnings.extend(import_warnings),
+                Err(err) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({ "error": format!("Import failed: {}", err) })),
+                    );
+                }
+            }
+            continue;
+        }
+
+        let file_path = dir_path.join(&file_name);
+        let mut file = File::create(&file_path).expect("Error creating file");
+        file.write_all(&data).expect("Error writing to file");
+
         if file_name.ends_with(".zip") {
-            let target_dir = PathBuf::from(format!("{}/{}", dir_path, "testcases"));
+            let target_dir = dir_path.join("testcases");
             if target_dir.exists() {
                 fs::remove_dir_all(&target_dir).unwrap_or_else(|e| {
                     eprintln!("Error deleting directory: {}", e);
@@ -80,13 +115,94 @@ pub async fn upload_task(
                 .expect("Error extracting zip file");
         }
     }
-    return (StatusCode::OK, Json(json!({ "message": "ok" })));
+
+    let problems = validate_task_id(&task_id);
+    (
+        StatusCode::OK,
+        Json(json!({ "message": "ok", "warnings": warnings, "problems": problems })),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    format: Option<String>,
+}
+
+/// Exports a task as a package zip, in the Kattis format unless asked otherwise.
+pub async fn export_task(
+    Path(task_id): Path<String>,
+    Query(query): Query<ExportQuery>
+) -> impl IntoResponse {
+    if get_task_config(task_id.clone()).is_err() {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": "Invalid task id" }))).into_response();
+    }
+    let format = query.format.unwrap_or("kattis".to_string());
+
+    match export_package(&format, &task_id) {
+        Ok((data, warnings)) => {
+            for warning in warnings {
+                warn!(" [x] Export of {}: {}", task_id, warning);
+            }
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/zip")
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}.zip\"", task_id)
+                )
+                .body(data.into())
+                .unwrap()
+        }
+        Err(err) => {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": format!("Export failed: {}", err) })),
+            ).into_response()
+        }
+    }
 }
 
 pub async fn delete_task(Path(task_id): Path<String>) -> impl IntoResponse {
-    let dir_path = format!("tasks/{}", task_id);
+    let dir_path = task_dir(&task_id);
     fs::remove_dir_all(&dir_path).unwrap_or_else(|e| {
         eprintln!("Error deleting directory: {}", e);
     });
-    return (StatusCode::OK, Json(json!({ "message": "ok" })));
+    (StatusCode::OK, Json(json!({ "message": "ok" })))
+}
+
+/// Room for the last run started within the job's time budget.
+const CALIBRATE_TIMEOUT: Duration = CALIBRATION_TIME_BUDGET.saturating_add(Duration::from_secs(60));
+
+/// Calibration can rewrite the manifest and holds a job worker for minutes, so it is for
+/// admins only.
+pub async fn calibrate_task(
+    headers: HeaderMap,
+    Path(task_id): Path<String>,
+    Json(mut req): Json<CalibrateJob>,
+    state: Arc<AppState>
+) -> impl IntoResponse {
+    if let Err(refused) = require_admin(&headers) {
+        return refused;
+    }
+    if get_task_config(task_id.clone()).is_err() {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": "Invalid task id" })));
+    }
+    // A smaller factor would suggest a limit below the model solution's own worst time.
+    if req.factor.is_nan() || req.factor < 1.0 {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": "Factor must be at least 1" })));
+    }
+    req.task_id = task_id;
+
+    let reply = rbmq::call_job(
+        state.channel(),
+        settings().job_queue_name.clone(),
+        &Job::Calibrate(req),
+        CALIBRATE_TIMEOUT
+    ).await;
+
+    match reply {
+        Ok(Ok(output)) => (StatusCode::OK, Json(output)),
+        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": err }))),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": err.to_string() }))),
+    }
 }
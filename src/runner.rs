@@ -1,217 +1,872 @@
-use std::{ fs, path::PathBuf, cmp };
-use log::info;
-use anyhow::Result;
+use std::{ cmp, collections::{ HashMap, HashSet }, path::PathBuf };
+use log::{ info, warn };
+use anyhow::{ anyhow, Result };
 
-use crate::helper::{ get_language_config, get_task_config };
-use crate::isolate::{ Isolate, RunVerdict };
+use crate::helper::{
+    get_language_config,
+    get_task_config,
+    FeedbackLevel,
+    JudgeMode,
+    RerunPolicy,
+    TaskConfig,
+};
+use crate::validate::validate_task_id;
+use crate::isolate::{ Isolate, Limits, RunVerdict };
 use serde::{ Deserialize, Serialize };
+use serde_json::{ json, Value };
+use tokio::sync::mpsc::UnboundedSender;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Machine-readable verdict of a test or a whole submission. Serialized as its short code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Verdict {
+    #[serde(rename = "AC")]
+    Accepted,
+    #[serde(rename = "PA")]
+    PartiallyAccepted,
+    #[serde(rename = "WA")]
+    WrongAnswer,
+    #[serde(rename = "TLE")]
+    TimeLimitExceeded,
+    #[serde(rename = "MLE")]
+    MemoryLimitExceeded,
+    #[serde(rename = "OLE")]
+    OutputLimitExceeded,
+    #[serde(rename = "RE")]
+    RuntimeError,
+    #[serde(rename = "SG")]
+    SignalError,
+    #[serde(rename = "IE")]
+    InternalError,
+    #[serde(rename = "SK")]
+    Skipped,
+    #[serde(rename = "CE")]
+    CompilationError,
+    // Results stored before the rename still say "TCE".
+    #[serde(rename = "TM", alias = "TCE")]
+    TaskMisconfigured,
+    #[serde(rename = "JE")]
+    JudgeError,
+}
+
+impl Verdict {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Verdict::Accepted => "AC",
+            Verdict::PartiallyAccepted => "PA",
+            Verdict::WrongAnswer => "WA",
+            Verdict::TimeLimitExceeded => "TLE",
+            Verdict::MemoryLimitExceeded => "MLE",
+            Verdict::OutputLimitExceeded => "OLE",
+            Verdict::RuntimeError => "RE",
+            Verdict::SignalError => "SG",
+            Verdict::InternalError => "IE",
+            Verdict::Skipped => "SK",
+            Verdict::CompilationError => "CE",
+            Verdict::TaskMisconfigured => "TM",
+            Verdict::JudgeError => "JE",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Verdict::Accepted => "Accepted",
+            Verdict::PartiallyAccepted => "Partially Accepted",
+            Verdict::WrongAnswer => "Wrong Answer",
+            Verdict::TimeLimitExceeded => "Time Limit Exceeded",
+            Verdict::MemoryLimitExceeded => "Memory Limit Exceeded",
+            Verdict::OutputLimitExceeded => "Output Limit Exceeded",
+            Verdict::RuntimeError => "Runtime Error",
+            Verdict::SignalError => "Signal Error",
+            Verdict::InternalError => "Internal Error",
+            Verdict::Skipped => "Skipped",
+            Verdict::CompilationError => "Compilation Error",
+            Verdict::TaskMisconfigured => "Task Misconfigured",
+            Verdict::JudgeError => "Judge Error",
+        }
+    }
+}
+
+impl From<RunVerdict> for Verdict {
+    fn from(verdict: RunVerdict) -> Self {
+        match verdict {
+            RunVerdict::VerdictOK => Verdict::Accepted,
+            RunVerdict::VerdictTLE => Verdict::TimeLimitExceeded,
+            RunVerdict::VerdictMLE => Verdict::MemoryLimitExceeded,
+            RunVerdict::VerdictOLE => Verdict::OutputLimitExceeded,
+            RunVerdict::VerdictRE => Verdict::RuntimeError,
+            RunVerdict::VerdictSG => Verdict::SignalError,
+            RunVerdict::VerdictXX => Verdict::InternalError,
+            RunVerdict::CompilationError => Verdict::CompilationError,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RunResult {
     pub status: String,
+    pub verdict: Verdict,
     pub test_index: u64,
     pub subtask_index: u64,
     pub score: u64,
     pub time: f64,
     pub memory: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<SampleFeedback>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<Attempt>,
+}
+
+/// One run of a test that was judged more than once because it was near the time limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attempt {
+    pub verdict: Verdict,
+    pub time: f64,
+    pub memory: u64,
+}
+
+impl From<&TestOutcome> for Attempt {
+    fn from(outcome: &TestOutcome) -> Self {
+        Attempt {
+            verdict: outcome.verdict,
+            time: outcome.time,
+            memory: outcome.memory,
+        }
+    }
+}
+
+/// Full feedback shown for sample tests. Each field is cut to `SAMPLE_FEEDBACK_LIMIT` bytes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleFeedback {
+    pub input: String,
+    pub expected: String,
+    pub output: String,
+}
+
+const SAMPLE_FEEDBACK_LIMIT: u64 = 1024;
+
+impl RunResult {
+    fn new(outcome: TestOutcome, test_index: u64, subtask_index: u64, score: u64) -> Self {
+        RunResult {
+            status: outcome.verdict.description().to_string(),
+            verdict: outcome.verdict,
+            test_index,
+            subtask_index,
+            score,
+            time: outcome.time,
+            memory: outcome.memory,
+            sample: outcome.sample,
+            attempts: outcome.attempts,
+        }
+    }
 }
 
 pub struct JudgeResult {
     pub result: Vec<RunResult>,
     pub status: String,
+    pub verdict: Verdict,
     pub score: u64,
     pub time: u64,
     pub memory: u64,
+    pub feedback: FeedbackLevel,
+}
+
+#[derive(Clone)]
+struct TestOutcome {
+    verdict: Verdict,
+    passed: bool,
+    time: f64,
+    memory: u64,
+    sample: Option<SampleFeedback>,
+    attempts: Vec<Attempt>,
+}
+
+/// Judges a test and, when the task has a rerun policy, runs it again while the best run so
+/// far is near the time limit. For samples the feedback is read after every run, so it shows
+/// the output of the run that counts.
+async fn judge_test(
+    isolate: &mut Isolate,
+    test_index: u64,
+    limits: &Limits,
+    rerun: Option<RerunPolicy>,
+    sample: bool
+) -> Result<TestOutcome> {
+    let mut outcome = judge_once(isolate, test_index, limits, sample).await?;
+
+    if let Some(rerun) = rerun {
+        let mut attempts = vec![];
+        while
+            (attempts.len() as u64) <= rerun.max_reruns &&
+            is_near_limit(&rerun, &outcome, limits.time_limit)
+        {
+            if attempts.is_empty() {
+                attempts.push(Attempt::from(&outcome));
+            }
+            let next = judge_once(isolate, test_index, limits, sample).await?;
+            attempts.push(Attempt::from(&next));
+            if is_better_run(&next, &outcome) {
+                outcome = next;
+            }
+        }
+        outcome.attempts = attempts;
+    }
+
+    Ok(outcome)
+}
+
+/// A passing run beats a failing one. Between runs with the same verdict the faster one
+/// wins; otherwise the earlier run is kept, so a rerun never swaps one failure for another.
+fn is_better_run(next: &TestOutcome, best: &TestOutcome) -> bool {
+    if next.passed != best.passed {
+        return next.passed;
+    }
+    next.verdict == best.verdict && next.time < best.time
+}
+
+fn is_near_limit(rerun: &RerunPolicy, outcome: &TestOutcome, time_limit: f64) -> bool {
+    let timed_out = outcome.verdict == Verdict::TimeLimitExceeded;
+    (timed_out && outcome.time <= time_limit * (1.0 + rerun.margin)) ||
+        (outcome.passed && outcome.time >= time_limit * (1.0 - rerun.margin))
+}
+
+async fn judge_once(
+    isolate: &mut Isolate,
+    test_index: u64,
+    limits: &Limits,
+    sample: bool
+) -> Result<TestOutcome> {
+    let isolate_result = isolate.run(test_index, limits).await?;
+
+    let passed = isolate_result.status == RunVerdict::VerdictOK && isolate.check(test_index).await?;
+    let verdict = if isolate_result.status == RunVerdict::VerdictOK && !passed {
+        Verdict::WrongAnswer
+    } else {
+        Verdict::from(isolate_result.status)
+    };
+
+    let sample = match sample {
+        true => Some(sample_feedback(isolate, test_index)?),
+        false => None,
+    };
+
+    Ok(TestOutcome {
+        verdict,
+        passed,
+        time: isolate_result.time_usage,
+        memory: isolate_result.memory_usage,
+        sample,
+        attempts: vec![],
+    })
+}
+
+fn sample_feedback(isolate: &Isolate, test_index: u64) -> Result<SampleFeedback> {
+    let read = |name: &str| isolate.read_box_file(name, SAMPLE_FEEDBACK_LIMIT);
+    Ok(SampleFeedback {
+        input: read(&format!("{}.in", test_index))?,
+        expected: read(&format!("{}.sol", test_index))?,
+        output: read("out.out").unwrap_or_default(),
+    })
+}
+
+/// Sent after every finished test so the caller can show how far judging has got. A sample
+/// is sent once when it is judged ahead of the rest and again, with its score, when the main
+/// pass reaches it.
+#[derive(Debug)]
+pub struct Progress {
+    pub tests_done: u64,
+    pub num_testcases: u64,
+    pub result: RunResult,
+    pub feedback: FeedbackLevel,
+}
+
+/// State shared by the judging passes: the box, the sample outcomes judged ahead of the
+/// main pass and where to send progress.
+struct TestRunner<'a> {
+    isolate: &'a mut Isolate,
+    samples: HashMap<u64, TestOutcome>,
+    rerun: Option<RerunPolicy>,
+    progress: Option<UnboundedSender<Progress>>,
+    feedback: FeedbackLevel,
+    num_testcases: u64,
+    reported: HashSet<u64>,
+}
+
+impl TestRunner<'_> {
+    /// Judges the sample tests before anything else and keeps their outcomes, together with
+    /// the contestant's output, until the main pass reaches them.
+    async fn judge_samples(&mut self, task_config: &TaskConfig) -> Result<()> {
+        for test_index in 1..=task_config.num_testcases {
+            if !task_config.is_sample(test_index) {
+                continue;
+            }
+            let (subtask_index, subtask) = match task_config.subtask_of(test_index) {
+                Some((subtask_index, subtask)) => (subtask_index, Some(subtask)),
+                None => (0, None),
+            };
+            let limits = task_config.limits(subtask, test_index);
+            let outcome = judge_test(self.isolate, test_index, &limits, self.rerun, true).await?;
+            self.report(&RunResult::new(outcome.clone(), test_index, subtask_index, 0));
+            self.samples.insert(test_index, outcome);
+        }
+
+        Ok(())
+    }
+
+    async fn judge(&mut self, test_index: u64, limits: &Limits) -> Result<TestOutcome> {
+        match self.samples.remove(&test_index) {
+            Some(outcome) => Ok(outcome),
+            None => judge_test(self.isolate, test_index, limits, self.rerun, false).await,
+        }
+    }
+
+    fn is_pending_sample(&self, test_index: u64) -> bool {
+        self.samples.contains_key(&test_index)
+    }
+
+    fn report(&mut self, result: &RunResult) {
+        self.reported.insert(result.test_index);
+        if let Some(progress) = &self.progress {
+            // The receiver going away only means nobody is watching any more.
+            let _ = progress.send(Progress {
+                tests_done: self.reported.len() as u64,
+                num_testcases: self.num_testcases,
+                result: result.clone(),
+                feedback: self.feedback,
+            });
+        }
+    }
+}
+
+impl JudgeResult {
+    fn add_usage(&mut self, outcome: &TestOutcome) {
+        self.memory = cmp::max(self.memory, outcome.memory);
+        self.time = cmp::max(self.time, (outcome.time * 1000.0) as u64);
+    }
+
+    fn set_verdict(&mut self, verdict: Verdict) {
+        self.verdict = verdict;
+        self.status = verdict.description().to_string();
+    }
+
+    /// The status for contestants. Levels that hide single tests only get the verdict, not
+    /// the test it happened on.
+    pub fn public_status(&self) -> String {
+        match self.feedback {
+            FeedbackLevel::Full | FeedbackLevel::FirstFailure => self.status.clone(),
+            FeedbackLevel::SubtaskSummary | FeedbackLevel::None => {
+                self.verdict.description().to_string()
+            }
+        }
+    }
+
+    /// The per-test results filtered by the task's feedback level, for contestants.
+    pub fn public_result(&self) -> Result<Value> {
+        public_result(&self.result, self.feedback)
+    }
+
+
+    /// Accepted when every judged test that counts passed, Partially Accepted when some points
+    /// were earned, otherwise the most frequent failing verdict (the earliest one on a tie).
+    fn aggregate_verdict(&self, task_config: &TaskConfig) -> Verdict {
+        let mut failures: Vec<(Verdict, usize)> = vec![];
+        for result in &self.result {
+            if
+                result.verdict == Verdict::Accepted ||
+                result.verdict == Verdict::Skipped ||
+                !task_config.counts_score(result.test_index)
+            {
+                continue;
+            }
+            match failures.iter_mut().find(|(verdict, _)| *verdict == result.verdict) {
+                Some((_, count)) => {
+                    *count += 1;
+                }
+                None => failures.push((result.verdict, 1)),
+            }
+        }
+
+        let mut dominant: Option<(Verdict, usize)> = None;
+        for (verdict, count) in failures {
+            if dominant.is_none_or(|(_, max)| count > max) {
+                dominant = Some((verdict, count));
+            }
+        }
+
+        match dominant {
+            None => Verdict::Accepted,
+            Some(_) if self.score > 0 => Verdict::PartiallyAccepted,
+            Some((verdict, _)) => verdict,
+        }
+    }
+}
+
+/// Results filtered by a feedback level, for contestants. Sample tests are always shown in
+/// full unless the level is `none`.
+pub fn public_result(results: &[RunResult], feedback: FeedbackLevel) -> Result<Value> {
+    let samples = results.iter().filter(|result| result.sample.is_some());
+
+    let view = match feedback {
+        FeedbackLevel::Full => serde_json::to_value(results)?,
+        FeedbackLevel::SubtaskSummary => {
+            let mut summaries: Vec<(u64, u64, Verdict)> = vec![];
+            for result in results {
+                let failed =
+                    result.verdict != Verdict::Accepted && result.verdict != Verdict::Skipped;
+                match summaries.last_mut() {
+                    Some((subtask_index, score, verdict)) if
+                        *subtask_index == result.subtask_index
+                    => {
+                        *score += result.score;
+                        if failed && *verdict == Verdict::Accepted {
+                            *verdict = result.verdict;
+                        }
+                    }
+                    _ => {
+                        let verdict = if failed { result.verdict } else { Verdict::Accepted };
+                        summaries.push((result.subtask_index, result.score, verdict));
+                    }
+                }
+            }
+
+            let summaries = summaries
+                .into_iter()
+                .map(|(subtask_index, score, verdict)| {
+                    json!({
+                        "subtask_index": subtask_index,
+                        "score": score,
+                        "status": verdict.description(),
+                        "verdict": verdict,
+                    })
+                });
+            json!({
+                "subtasks": summaries.collect::<Vec<_>>(),
+                "samples": samples.collect::<Vec<_>>(),
+            })
+        }
+        FeedbackLevel::FirstFailure => {
+            let first_failure = results
+                .iter()
+                .find(|result| {
+                    result.sample.is_none() &&
+                        result.verdict != Verdict::Accepted &&
+                        result.verdict != Verdict::Skipped
+                })
+                .map(|result| {
+                    json!({
+                        "test_index": result.test_index,
+                        "subtask_index": result.subtask_index,
+                        "status": result.status,
+                        "verdict": result.verdict,
+                    })
+                });
+            json!({
+                "first_failure": first_failure,
+                "samples": samples.collect::<Vec<_>>(),
+            })
+        }
+        FeedbackLevel::None => json!({}),
+    };
+
+    Ok(view)
 }
 
-async fn is_testcases_error(task_path: &str, num_testcases: u64) -> bool {
-    for i in 1..=num_testcases {
-        let input_file = format!("{}/{}.in", task_path, i);
-        let output_file = format!("{}/{}.sol", task_path, i);
+/// Runs every test in order and stops at the first one that does not pass. The score is
+/// all or nothing.
+async fn run_icpc(
+    runner: &mut TestRunner<'_>,
+    task_config: &TaskConfig,
+    judge_result: &mut JudgeResult
+) -> Result<()> {
+    judge_result.set_verdict(Verdict::Accepted);
 
-        if !fs::metadata(&input_file).is_ok() || !fs::metadata(&output_file).is_ok() {
-            return true;
+    for test_index in 1..=task_config.num_testcases {
+        let (subtask_index, subtask) = match task_config.subtask_of(test_index) {
+            Some((subtask_index, subtask)) => (subtask_index, Some(subtask)),
+            None => (0, None),
+        };
+        let limits = task_config.limits(subtask, test_index);
+        let outcome = runner.judge(test_index, &limits).await?;
+        let passed = outcome.passed;
+        let verdict = outcome.verdict;
+
+        judge_result.add_usage(&outcome);
+        let result = RunResult::new(outcome, test_index, subtask_index, 0);
+        runner.report(&result);
+        judge_result.result.push(result);
+
+        if !passed {
+            judge_result.verdict = verdict;
+            judge_result.status = format!("{} on test {}", verdict.description(), test_index);
+            return Ok(());
         }
     }
-    false
+
+    judge_result.score = task_config.full_score;
+    Ok(())
 }
 
-fn get_status(verdict: RunVerdict) -> String {
-    match verdict {
-        RunVerdict::VerdictOK => "Accepted".to_string(),
-        RunVerdict::VerdictTLE => "Time Limit Exceeded".to_string(),
-        RunVerdict::VerdictMLE => "Memory Limit Exceeded".to_string(),
-        RunVerdict::VerdictRE => "Runtime Error".to_string(),
-        RunVerdict::VerdictSG => "Signal Error".to_string(),
-        RunVerdict::VerdictXX => "Internal Error".to_string(),
-        _ => "".to_string(),
+/// Scores each test on its own, or each subtask as all or nothing when the task has subtasks.
+async fn run_ioi(
+    runner: &mut TestRunner<'_>,
+    task_config: &TaskConfig,
+    judge_result: &mut JudgeResult
+) -> Result<()> {
+    let subtasks = &task_config.subtasks;
+    let use_skip = task_config.skip;
+    let mut test_index = 1;
+
+    if subtasks.is_empty() {
+        let scored_tests = (1..=task_config.num_testcases)
+            .filter(|&test_index| task_config.counts_score(test_index))
+            .count() as u64;
+
+        for _ in 1..=task_config.num_testcases {
+            let limits = task_config.limits(None, test_index);
+            let outcome = runner.judge(test_index, &limits).await?;
+            let score = if outcome.passed && task_config.counts_score(test_index) {
+                task_config.full_score / scored_tests
+            } else {
+                0
+            };
+
+            judge_result.score += score;
+            judge_result.add_usage(&outcome);
+            let result = RunResult::new(outcome, test_index, 0, score);
+            runner.report(&result);
+            judge_result.result.push(result);
+
+            test_index += 1;
+        }
+    } else {
+        for (subtask_index, subtask) in (1..).zip(subtasks) {
+            let first_test = test_index;
+            let scored_tests = (first_test..first_test + subtask.num_testcases)
+                .filter(|&test_index| task_config.counts_score(test_index))
+                .count() as u64;
+            let mut correct_all = true;
+            let mut skipped = false;
+            let mut subtask_result = vec![];
+
+            for _ in 0..subtask.num_testcases {
+                let counts_score = task_config.counts_score(test_index);
+
+                let result = if use_skip && skipped && !runner.is_pending_sample(test_index) {
+                    RunResult {
+                        status: Verdict::Skipped.description().to_string(),
+                        verdict: Verdict::Skipped,
+                        test_index,
+                        subtask_index,
+                        score: 0,
+                        time: 0.0,
+                        memory: 0,
+                        sample: None,
+                        attempts: vec![],
+                    }
+                } else {
+                    let limits = task_config.limits(Some(subtask), test_index);
+                    let outcome = runner.judge(test_index, &limits).await?;
+                    let mut score = if counts_score {
+                        subtask.full_score / scored_tests
+                    } else {
+                        0
+                    };
+                    if !outcome.passed {
+                        if counts_score {
+                            correct_all = false;
+                            skipped = true;
+                        }
+                        score = 0;
+                    }
+
+                    judge_result.add_usage(&outcome);
+                    RunResult::new(outcome, test_index, subtask_index, score)
+                };
+                runner.report(&result);
+                subtask_result.push(result);
+                test_index += 1;
+            }
+
+            if correct_all && scored_tests > 0 {
+                judge_result.score += subtask.full_score;
+            } else {
+                for result in &mut subtask_result {
+                    result.score = 0;
+                }
+            }
+
+            judge_result.result.append(&mut subtask_result);
+        }
     }
+
+    Ok(())
 }
 
 pub async fn run(
     task_id: String,
-    submission_id: u64,
+    box_id: u64,
     code: String,
-    language: String
+    language: String,
+    progress: Option<UnboundedSender<Progress>>
 ) -> Result<JudgeResult> {
-    let language_config = get_language_config(&language).unwrap();
+    let language_config = get_language_config(&language)
+        .map_err(|err| anyhow!("{}: {}", err, language))?;
+
+    let problems = validate_task_id(&task_id);
+    if !problems.is_empty() {
+        warn!("Task {} is misconfigured: {}", task_id, problems.join("; "));
+        let mut judge_result = JudgeResult {
+            result: vec![],
+            status: String::new(),
+            verdict: Verdict::TaskMisconfigured,
+            score: 0,
+            time: 0,
+            memory: 0,
+            feedback: FeedbackLevel::default(),
+        };
+        judge_result.set_verdict(Verdict::TaskMisconfigured);
+        return Ok(judge_result);
+    }
     let task_config = get_task_config(task_id.clone())?;
 
     let mut isolate = Isolate {
         box_path: PathBuf::new(),
-        box_id: submission_id % 1000,
-        time_limit: task_config.time_limit,
-        memory_limit: task_config.memory_limit * 1000,
+        box_id,
         task_id: task_id.clone(),
         code,
         ext: language_config.ext,
         compile_script: language_config.compile,
         run_script: language_config.run,
-        checker: task_config.checker,
+        checker: task_config.checker.clone(),
+        checker_type: task_config.checker_type,
     };
 
     let mut judge_result = JudgeResult {
         result: vec![],
         status: "Completed".to_string(),
+        verdict: Verdict::Accepted,
         score: 0,
         time: 0,
         memory: 0,
+        feedback: task_config.feedback,
     };
 
-    let task_path = format!("tasks/{}/testcases", task_id);
+    // The box is cleaned up however judging ends, so a retry does not find it dirty.
+    let judged = judge_in_box(&mut isolate, &task_config, progress, &mut judge_result).await;
+    isolate.cleanup().await?;
+    judged?;
 
-    if is_testcases_error(&task_path, task_config.num_testcases).await {
-        judge_result.status = "Testcases Error".to_string();
-        return Ok(judge_result);
-    }
+    let judge_result_json = serde_json::to_string(&judge_result.result)?;
 
+    info!("{:?}", judge_result_json);
+
+    Ok(judge_result)
+}
+
+async fn judge_in_box(
+    isolate: &mut Isolate,
+    task_config: &TaskConfig,
+    progress: Option<UnboundedSender<Progress>>,
+    judge_result: &mut JudgeResult
+) -> Result<()> {
     isolate.init().await?;
+    isolate.copy_testcases().await?;
     let compile_result = isolate.compile().await?;
 
     if compile_result.status == RunVerdict::CompilationError {
-        judge_result.status = "Compilation Error".to_string();
+        judge_result.set_verdict(Verdict::CompilationError);
+        return Ok(());
+    }
+
+    let mut runner = TestRunner {
+        isolate,
+        samples: HashMap::new(),
+        rerun: task_config.rerun,
+        progress,
+        feedback: task_config.feedback,
+        num_testcases: task_config.num_testcases,
+        reported: HashSet::new(),
+    };
+    runner.judge_samples(task_config).await?;
+
+    if task_config.mode == JudgeMode::Icpc {
+        run_icpc(&mut runner, task_config, judge_result).await?;
     } else {
-        let subtasks = task_config.subtasks;
-        let use_skip = task_config.skip;
-        let mut test_index = 1;
-
-        if subtasks.is_empty() {
-            for _ in 1..=task_config.num_testcases {
-                let mut score = task_config.full_score / task_config.num_testcases;
-                let isolate_result = isolate.run(test_index).await?;
-                let mut correct = true;
-                if isolate_result.status == RunVerdict::VerdictOK {
-                    if !isolate.check(test_index).await? {
-                        score = 0;
-                        correct = false;
-                    }
-                } else {
-                    score = 0;
-                }
-                let status = if correct {
-                    get_status(isolate_result.status)
-                } else {
-                    "Wrong Answer".to_string()
-                };
+        run_ioi(&mut runner, task_config, judge_result).await?;
+        judge_result.set_verdict(judge_result.aggregate_verdict(task_config));
+    }
 
-                judge_result.score += score;
-                judge_result.memory = cmp::max(judge_result.memory, isolate_result.memory_usage);
-                judge_result.time = cmp::max(
-                    judge_result.time,
-                    (isolate_result.time_usage * 1000.0) as u64
-                );
-
-                judge_result.result.push(RunResult {
-                    status,
-                    test_index,
-                    subtask_index: 0,
-                    score,
-                    time: isolate_result.time_usage,
-                    memory: isolate_result.memory_usage,
-                });
+    Ok(())
+}
 
-                test_index += 1;
-            }
-        } else {
-            let mut subtask_index = 1;
-
-            for subtask in subtasks {
-                let mut correct_all = true;
-                let mut skipped = false;
-                let mut subtask_result = vec![];
-
-                for _ in 0..subtask.num_testcases {
-                    if use_skip && skipped {
-                        subtask_result.push(RunResult {
-                            status: "Skipped".to_string(),
-                            test_index,
-                            subtask_index,
-                            score: 0,
-                            time: 0.0,
-                            memory: 0,
-                        });
-                    } else {
-                        let isolate_result = isolate.run(test_index).await?;
-                        let mut correct = true;
-                        let mut score = subtask.full_score / subtask.num_testcases;
-                        if isolate_result.status == RunVerdict::VerdictOK {
-                            if !isolate.check(test_index).await? {
-                                correct_all = false;
-                                skipped = true;
-                                correct = false;
-                                score = 0;
-                            }
-                        } else {
-                            correct_all = false;
-                            skipped = true;
-                            score = 0;
-                        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                        let status = if correct {
-                            get_status(isolate_result.status)
-                        } else {
-                            "Wrong Answer".to_string()
-                        };
-
-                        judge_result.memory = cmp::max(
-                            judge_result.memory,
-                            isolate_result.memory_usage
-                        );
-                        judge_result.time = cmp::max(
-                            judge_result.time,
-                            (isolate_result.time_usage * 1000.0) as u64
-                        );
-
-                        subtask_result.push(RunResult {
-                            status,
-                            test_index,
-                            subtask_index,
-                            score,
-                            time: isolate_result.time_usage,
-                            memory: isolate_result.memory_usage,
-                        });
-                    }
-                    test_index += 1;
-                }
+    fn result(test_index: u64, subtask_index: u64, verdict: Verdict, score: u64) -> RunResult {
+        RunResult {
+            status: verdict.description().to_string(),
+            verdict,
+            test_index,
+            subtask_index,
+            score,
+            time: 0.1,
+            memory: 1024,
+            sample: None,
+            attempts: vec![],
+        }
+    }
 
-                if correct_all {
-                    judge_result.score += subtask.full_score;
-                } else {
-                    for result in &mut subtask_result {
-                        result.score = 0;
-                    }
-                }
+    fn judge_result(result: Vec<RunResult>) -> JudgeResult {
+        JudgeResult {
+            score: result.iter().map(|result| result.score).sum(),
+            result,
+            status: String::new(),
+            verdict: Verdict::Accepted,
+            time: 0,
+            memory: 0,
+            feedback: FeedbackLevel::Full,
+        }
+    }
 
-                judge_result.result.append(&mut subtask_result);
-                subtask_index += 1;
-            }
+    fn outcome(verdict: Verdict, time: f64) -> TestOutcome {
+        TestOutcome {
+            verdict,
+            passed: verdict == Verdict::Accepted,
+            time,
+            memory: 0,
+            sample: None,
+            attempts: vec![],
         }
     }
 
-    let judge_result_json = serde_json::to_string(&judge_result.result)?;
+    #[test]
+    fn aggregate_verdict_is_the_most_frequent_failure() {
+        let task_config = TaskConfig::default();
+        let verdicts = [
+            Verdict::Accepted,
+            Verdict::WrongAnswer,
+            Verdict::TimeLimitExceeded,
+            Verdict::TimeLimitExceeded,
+            Verdict::Skipped,
+        ];
+        let results = (1..).zip(verdicts).map(|(index, verdict)| result(index, 0, verdict, 0));
+        let judge_result = judge_result(results.collect());
 
-    info!("{:?}", judge_result_json);
+        assert_eq!(judge_result.aggregate_verdict(&task_config), Verdict::TimeLimitExceeded);
+    }
 
-    isolate.cleanup().await?;
-    Ok(judge_result)
+    #[test]
+    fn aggregate_verdict_breaks_ties_by_the_earliest_failure() {
+        let task_config = TaskConfig::default();
+        let judge_result = judge_result(
+            vec![
+                result(1, 0, Verdict::RuntimeError, 0),
+                result(2, 0, Verdict::WrongAnswer, 0)
+            ]
+        );
+
+        assert_eq!(judge_result.aggregate_verdict(&task_config), Verdict::RuntimeError);
+    }
+
+    #[test]
+    fn aggregate_verdict_with_points_is_partially_accepted() {
+        let task_config = TaskConfig::default();
+        let judge_result = judge_result(
+            vec![result(1, 0, Verdict::Accepted, 50), result(2, 0, Verdict::WrongAnswer, 0)]
+        );
+
+        assert_eq!(judge_result.aggregate_verdict(&task_config), Verdict::PartiallyAccepted);
+    }
+
+    #[test]
+    fn aggregate_verdict_ignores_excluded_samples() {
+        let task_config = TaskConfig {
+            samples: vec![1],
+            exclude_sample_score: true,
+            ..Default::default()
+        };
+        let judge_result = judge_result(
+            vec![result(1, 0, Verdict::WrongAnswer, 0), result(2, 0, Verdict::Accepted, 100)]
+        );
+
+        assert_eq!(judge_result.aggregate_verdict(&task_config), Verdict::Accepted);
+    }
+
+    #[test]
+    fn public_result_summarizes_subtasks() {
+        let results = vec![
+            result(1, 1, Verdict::Accepted, 10),
+            result(2, 1, Verdict::Accepted, 10),
+            result(3, 2, Verdict::Accepted, 0),
+            result(4, 2, Verdict::WrongAnswer, 0),
+            result(5, 2, Verdict::TimeLimitExceeded, 0)
+        ];
+
+        let view = public_result(&results, FeedbackLevel::SubtaskSummary).unwrap();
+        assert_eq!(view["subtasks"][0]["score"], 20);
+        assert_eq!(view["subtasks"][0]["verdict"], "AC");
+        assert_eq!(view["subtasks"][1]["verdict"], "WA");
+        assert_eq!(view["samples"], json!([]));
+    }
+
+    #[test]
+    fn public_result_shows_the_first_failure_outside_the_samples() {
+        let mut sample = result(1, 0, Verdict::WrongAnswer, 0);
+        sample.sample = Some(SampleFeedback {
+            input: "1 2".to_string(),
+            expected: "3".to_string(),
+            output: "4".to_string(),
+        });
+        let results = vec![
+            sample,
+            result(2, 0, Verdict::Accepted, 0),
+            result(3, 0, Verdict::RuntimeError, 0),
+            result(4, 0, Verdict::WrongAnswer, 0)
+        ];
+
+        let view = public_result(&results, FeedbackLevel::FirstFailure).unwrap();
+        assert_eq!(view["first_failure"]["test_index"], 3);
+        assert_eq!(view["first_failure"]["verdict"], "RE");
+        assert_eq!(view["samples"][0]["sample"]["output"], "4");
+    }
+
+    #[test]
+    fn public_status_hides_the_failing_test_with_summary_feedback() {
+        let mut judge_result = judge_result(vec![result(1, 0, Verdict::WrongAnswer, 0)]);
+        judge_result.set_verdict(Verdict::WrongAnswer);
+        judge_result.status = "Wrong Answer on test 1".to_string();
+
+        assert_eq!(judge_result.public_status(), "Wrong Answer on test 1");
+        judge_result.feedback = FeedbackLevel::SubtaskSummary;
+        assert_eq!(judge_result.public_status(), "Wrong Answer");
+        assert_eq!(judge_result.status, "Wrong Answer on test 1");
+    }
+
+    #[test]
+    fn public_result_hides_everything_without_feedback() {
+        let results = vec![result(1, 0, Verdict::WrongAnswer, 0)];
+
+        assert_eq!(public_result(&results, FeedbackLevel::None).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn is_near_limit_within_the_margin() {
+        let rerun = RerunPolicy { margin: 0.1, max_reruns: 2 };
+
+        assert!(is_near_limit(&rerun, &outcome(Verdict::TimeLimitExceeded, 1.05), 1.0));
+        assert!(!is_near_limit(&rerun, &outcome(Verdict::TimeLimitExceeded, 1.2), 1.0));
+        assert!(is_near_limit(&rerun, &outcome(Verdict::Accepted, 0.95), 1.0));
+        assert!(!is_near_limit(&rerun, &outcome(Verdict::Accepted, 0.5), 1.0));
+        assert!(!is_near_limit(&rerun, &outcome(Verdict::WrongAnswer, 0.95), 1.0));
+    }
+
+    #[test]
+    fn is_better_run_prefers_a_pass_then_the_faster_run() {
+        let passed = outcome(Verdict::Accepted, 0.95);
+        let timed_out = outcome(Verdict::TimeLimitExceeded, 1.05);
+
+        assert!(is_better_run(&passed, &timed_out));
+        assert!(!is_better_run(&timed_out, &passed));
+        assert!(is_better_run(&outcome(Verdict::Accepted, 0.9), &passed));
+        assert!(!is_better_run(&outcome(Verdict::WrongAnswer, 0.1), &timed_out));
+    }
 }
@@ -0,0 +1,467 @@
+use std::fs;
+use std::path::Path;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::helper::{
+    checker_path,
+    get_language_config,
+    get_task_config,
+    migrate_manifest,
+    task_dir,
+    CheckerType,
+    Config,
+    Program,
+    TaskConfig,
+    MANIFEST_VERSION,
+    MAX_RERUNS,
+};
+use crate::import::compile_checker;
+use crate::isolate::MAX_JOB_WORKER;
+use crate::settings::Settings;
+
+/// Loads and validates `tasks/<task_id>`. A manifest that cannot be read is reported as a
+/// problem too.
+pub fn validate_task_id(task_id: &str) -> Vec<String> {
+    match get_task_config(task_id.to_string()) {
+        Ok(task_config) => {
+            validate_task(&task_dir(task_id), &task_config)
+        }
+        Err(err) => vec![format!("manifest.json cannot be read: {}", err)],
+    }
+}
+
+/// Checks a task directory against its manifest and returns every problem found. An empty
+/// list means the task can be judged.
+pub fn validate_task(task_dir: &Path, task_config: &TaskConfig) -> Vec<String> {
+    let mut problems = vec![];
+
+    if task_config.time_limit <= 0.0 {
+        problems.push("time_limit must be positive".to_string());
+    }
+    if task_config.memory_limit == 0 {
+        problems.push("memory_limit must be positive".to_string());
+    }
+    if task_config.output_limit == Some(0) {
+        problems.push("output_limit must be positive".to_string());
+    }
+    if task_config.num_testcases == 0 {
+        problems.push("num_testcases must be positive".to_string());
+    }
+
+    let testcases_dir = task_dir.join("testcases");
+    if !testcases_dir.is_dir() {
+        problems.push("testcases directory is missing".to_string());
+    } else {
+        for test_index in 1..=task_config.num_testcases {
+            for ext in ["in", "sol"] {
+                let file_name = format!("{}.{}", test_index, ext);
+                if !testcases_dir.join(&file_name).is_file() {
+                    problems.push(format!("testcases/{} is missing", file_name));
+                }
+            }
+        }
+    }
+
+    if !task_config.subtasks.is_empty() {
+        let num_testcases: u64 = task_config.subtasks
+            .iter()
+            .map(|subtask| subtask.num_testcases)
+            .sum();
+        if num_testcases != task_config.num_testcases {
+            problems.push(
+                format!(
+                    "subtasks have {} tests in total but num_testcases is {}",
+                    num_testcases,
+                    task_config.num_testcases
+                )
+            );
+        }
+
+        let full_score: u64 = task_config.subtasks
+            .iter()
+            .map(|subtask| subtask.full_score)
+            .sum();
+        if full_score != task_config.full_score {
+            problems.push(
+                format!(
+                    "subtask scores add up to {} but full_score is {}",
+                    full_score,
+                    task_config.full_score
+                )
+            );
+        }
+
+        for (subtask_index, subtask) in (1..).zip(&task_config.subtasks) {
+            if subtask.num_testcases == 0 {
+                problems.push(format!("subtask {} has no tests", subtask_index));
+            }
+        }
+    }
+
+    let checker = checker_path(task_dir, &task_config.checker, task_config.checker_type);
+    if !checker.is_file() {
+        problems.push(format!("checker {:?} does not exist", task_config.checker));
+    }
+
+    for &test_index in &task_config.samples {
+        if test_index == 0 || test_index > task_config.num_testcases {
+            problems.push(format!("sample test {} does not exist", test_index));
+        }
+    }
+    for test_index in task_config.test_limits.keys() {
+        if *test_index == 0 || *test_index > task_config.num_testcases {
+            problems.push(format!("test_limits refers to missing test {}", test_index));
+        }
+    }
+    let overrides = task_config.subtasks
+        .iter()
+        .map(|subtask| &subtask.limits)
+        .chain(task_config.test_limits.values());
+    for limits in overrides {
+        if
+            limits.time_limit.is_some_and(|limit| limit <= 0.0) ||
+            limits.memory_limit == Some(0) ||
+            limits.output_limit == Some(0)
+        {
+            problems.push("limit overrides must be positive".to_string());
+            break;
+        }
+    }
+
+    if let Some(rerun) = &task_config.rerun {
+        if rerun.margin <= 0.0 || rerun.margin >= 1.0 {
+            problems.push("rerun margin must be between 0 and 1".to_string());
+        }
+        if rerun.max_reruns == 0 || rerun.max_reruns > MAX_RERUNS {
+            problems.push(format!("rerun max_reruns must be between 1 and {}", MAX_RERUNS));
+        }
+    }
+
+    let programs = [
+        ("validator", &task_config.validator),
+        ("solution", &task_config.solution),
+    ];
+    for (name, program) in programs {
+        if let Some(program) = program {
+            validate_program(task_dir, name, program, &mut problems);
+        }
+    }
+
+    problems
+}
+
+fn validate_program(task_dir: &Path, name: &str, program: &Program, problems: &mut Vec<String>) {
+    if !task_dir.join(&program.file).is_file() {
+        problems.push(format!("{} {:?} does not exist", name, program.file));
+    }
+    if get_language_config(&program.language).is_err() {
+        problems.push(format!("{} language {:?} is not supported", name, program.language));
+    }
+}
+
+/// Checks config.json and returns every problem found.
+pub fn validate_config(config: &Config) -> Vec<String> {
+    let mut problems = vec![];
+
+    if config.language.is_empty() {
+        problems.push("no language is configured".to_string());
+    }
+
+    let mut languages: Vec<_> = config.language.iter().collect();
+    languages.sort_by_key(|(language, _)| language.as_str());
+    for (language, language_config) in languages {
+        if language_config.ext.is_empty() {
+            problems.push(format!("language {} has no ext", language));
+        }
+        if !language_config.compile.contains("{source_file}") {
+            problems.push(format!("compile of language {} has no {{source_file}}", language));
+        }
+        if !language_config.run.contains("{source}") {
+            problems.push(format!("run of language {} has no {{source}}", language));
+        }
+    }
+
+    problems
+}
+
+/// Checks the server settings and returns every problem found.
+pub fn validate_settings(settings: &Settings) -> Vec<String> {
+    let mut problems = vec![];
+
+    if settings.bind_address.parse::<std::net::SocketAddr>().is_err() {
+        problems.push(format!("bind_address {:?} is not an address", settings.bind_address));
+    }
+    if settings.queue_name.is_empty() || settings.job_queue_name.is_empty() {
+        problems.push("queue names must not be empty".to_string());
+    }
+    if settings.queue_name == settings.job_queue_name {
+        problems.push("queue_name and job_queue_name must differ".to_string());
+    }
+    if settings.max_job_worker > MAX_JOB_WORKER {
+        problems.push(format!("max_job_worker can be at most {}", MAX_JOB_WORKER));
+    }
+    if settings.prefetch == 0 {
+        problems.push("prefetch must be positive".to_string());
+    }
+    if settings.upload_limit == 0 {
+        problems.push("upload_limit must be positive".to_string());
+    }
+    if settings.heartbeat_interval == 0 {
+        problems.push("heartbeat_interval must be positive".to_string());
+    }
+    if settings.max_retries == 0 {
+        problems.push("max_retries must be positive".to_string());
+    }
+    if settings.max_attempts == 0 {
+        problems.push("max_attempts must be positive".to_string());
+    }
+    if settings.tasks_dir.exists() && !settings.tasks_dir.is_dir() {
+        problems.push(format!("tasks_dir {} is not a directory", settings.tasks_dir.display()));
+    }
+
+    problems
+}
+
+/// Findings of `lint_task`. Errors keep the task from being judged, warnings are likely
+/// mistakes.
+#[derive(Debug, Default, Serialize)]
+pub struct LintReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Checks a task directory more thoroughly than `validate_task`, for task repositories. Test
+/// files larger than `max_file_size` bytes are flagged.
+pub fn lint_task(task_dir: &Path, max_file_size: u64) -> LintReport {
+    let mut report = LintReport::default();
+
+    let manifest = fs::read_to_string(task_dir.join("manifest.json"))
+        .map_err(|err| err.to_string())
+        .and_then(|data| serde_json::from_str::<Value>(&data).map_err(|err| err.to_string()));
+    let mut manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            report.errors.push(format!("manifest.json cannot be read: {}", err));
+            return report;
+        }
+    };
+    match migrate_manifest(&mut manifest) {
+        Ok(version) if version < MANIFEST_VERSION => {
+            report.warnings.push(
+                format!("manifest.json is version {}, migrate it to {}", version, MANIFEST_VERSION)
+            );
+        }
+        Ok(_) => {}
+        Err(err) => {
+            report.errors.push(format!("manifest.json cannot be read: {}", err));
+            return report;
+        }
+    }
+    let task_config: TaskConfig = match serde_json::from_value(manifest) {
+        Ok(task_config) => task_config,
+        Err(err) => {
+            report.errors.push(format!("manifest.json cannot be read: {}", err));
+            return report;
+        }
+    };
+
+    report.errors = validate_task(task_dir, &task_config);
+    lint_checker(task_dir, &task_config, &mut report);
+    lint_testcases(task_dir, &task_config, max_file_size, &mut report);
+
+    report
+}
+
+/// A checker that is not built yet is fine as long as its source compiles.
+fn lint_checker(task_dir: &Path, task_config: &TaskConfig, report: &mut LintReport) {
+    let source = task_dir.join("checker.cpp");
+    if task_config.checker_type == CheckerType::Builtin || !source.is_file() {
+        return;
+    }
+
+    let output = std::env::temp_dir().join(format!("lint-checker-{}", std::process::id()));
+    let compiled = compile_checker(task_dir, "checker.cpp", &output.to_string_lossy(), task_dir);
+    let _ = fs::remove_file(&output);
+
+    let missing = format!("checker {:?} does not exist", task_config.checker);
+    match compiled {
+        Ok(()) => {
+            if report.errors.contains(&missing) {
+                report.errors.retain(|problem| problem != &missing);
+                report.warnings.push(
+                    format!(
+                        "checker {:?} is not built but checker.cpp compiles",
+                        task_config.checker
+                    )
+                );
+            }
+        }
+        Err(err) => report.errors.push(format!("checker.cpp does not compile: {}", err)),
+    }
+}
+
+fn lint_testcases(
+    task_dir: &Path,
+    task_config: &TaskConfig,
+    max_file_size: u64,
+    report: &mut LintReport
+) {
+    let Ok(entries) = fs::read_dir(task_dir.join("testcases")) else {
+        return;
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+
+    for file in &files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let Some((stem, ext)) = name.rsplit_once('.') else {
+            report.warnings.push(format!("testcases/{} is not a test file", name));
+            continue;
+        };
+        if ext != "in" && ext != "sol" {
+            report.warnings.push(format!("testcases/{} is not a test file", name));
+            continue;
+        }
+
+        // Missing files of the tests in the manifest are already reported by `validate_task`.
+        let in_manifest = stem
+            .parse::<u64>()
+            .is_ok_and(|test_index| test_index >= 1 && test_index <= task_config.num_testcases);
+        if !in_manifest {
+            report.warnings.push(
+                format!(
+                    "testcases/{} is not one of the {} tests in the manifest",
+                    name,
+                    task_config.num_testcases
+                )
+            );
+            let pair = if ext == "in" { "sol" } else { "in" };
+            if !task_dir.join("testcases").join(format!("{}.{}", stem, pair)).is_file() {
+                report.warnings.push(format!("testcases/{} has no matching .{}", name, pair));
+            }
+        }
+
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        if size == 0 && ext == "in" {
+            report.warnings.push(format!("testcases/{} is empty", name));
+        }
+        if size > max_file_size {
+            report.warnings.push(format!("testcases/{} is {} bytes", name, size));
+        } else if let Ok(data) = fs::read(file) {
+            if data.windows(2).any(|window| window == b"\r\n") {
+                report.warnings.push(format!("testcases/{} has CRLF line endings", name));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::helper::{ RerunPolicy, Subtask };
+
+    /// A task directory with two tests and a testlib checker, removed when dropped.
+    struct TaskDir(PathBuf);
+
+    impl TaskDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("validate-task-{}", Uuid::new_v4()));
+            fs::create_dir_all(dir.join("testcases")).unwrap();
+            for file_name in ["1.in", "1.sol", "2.in", "2.sol"] {
+                fs::write(dir.join("testcases").join(file_name), "1\n").unwrap();
+            }
+            fs::write(dir.join("checker"), "").unwrap();
+            TaskDir(dir)
+        }
+    }
+
+    impl Drop for TaskDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn task_config() -> TaskConfig {
+        TaskConfig {
+            time_limit: 1.0,
+            memory_limit: 262144,
+            checker: "checker".to_string(),
+            checker_type: CheckerType::Testlib,
+            full_score: 100,
+            num_testcases: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accepts_a_consistent_task() {
+        let task_dir = TaskDir::new();
+
+        assert_eq!(validate_task(&task_dir.0, &task_config()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_missing_tests_and_checker() {
+        let task_dir = TaskDir::new();
+        fs::remove_file(task_dir.0.join("testcases").join("2.sol")).unwrap();
+        fs::remove_file(task_dir.0.join("checker")).unwrap();
+
+        assert_eq!(
+            validate_task(&task_dir.0, &task_config()),
+            vec![
+                "testcases/2.sol is missing".to_string(),
+                "checker \"checker\" does not exist".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_subtasks_that_do_not_add_up() {
+        let task_dir = TaskDir::new();
+        let subtask = |full_score, num_testcases| Subtask {
+            full_score,
+            num_testcases,
+            ..Default::default()
+        };
+        let task_config = TaskConfig {
+            subtasks: vec![subtask(30, 2), subtask(30, 0)],
+            ..task_config()
+        };
+
+        assert_eq!(
+            validate_task(&task_dir.0, &task_config),
+            vec![
+                "subtask scores add up to 60 but full_score is 100".to_string(),
+                "subtask 2 has no tests".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_out_of_range_settings() {
+        let task_dir = TaskDir::new();
+        let task_config = TaskConfig {
+            time_limit: 0.0,
+            samples: vec![3],
+            rerun: Some(RerunPolicy { margin: 1.5, max_reruns: MAX_RERUNS + 1 }),
+            ..task_config()
+        };
+
+        assert_eq!(
+            validate_task(&task_dir.0, &task_config),
+            vec![
+                "time_limit must be positive".to_string(),
+                "sample test 3 does not exist".to_string(),
+                "rerun margin must be between 0 and 1".to_string(),
+                format!("rerun max_reruns must be between 1 and {}", MAX_RERUNS)
+            ]
+        );
+    }
+}